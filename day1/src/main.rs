@@ -1,47 +1,372 @@
-use std::collections::HashSet;
-use std::fs::read_to_string;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs::{read_to_string, File};
+use std::hash::Hash;
+use std::io::{BufWriter, Write};
 
 type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
-struct Solution {
-    part1: Option<i32>,
-    part2: Option<i32>,
+// Integer type the frequencies are accumulated in. All arithmetic is checked, an overflow is
+// reported as an error instead of silently wrapping.
+trait Frequency: Copy + Eq + Hash + From<i32> + fmt::Debug + fmt::Display {
+    fn parse(value: &str) -> Result<Self>;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_rem_euclid(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! impl_frequency {
+    ($($int:ty),*) => {
+        $(
+            impl Frequency for $int {
+                fn parse(value: &str) -> Result<Self> {
+                    Ok(value.parse::<$int>()?)
+                }
+
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$int>::checked_add(self, other)
+                }
+
+                fn checked_rem_euclid(self, other: Self) -> Option<Self> {
+                    <$int>::checked_rem_euclid(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_frequency!(i32, i64, i128);
+
+struct Solution<T> {
+    part1: Option<T>,
+    part2: Option<T>,
+    status: Status<T>,
+    repeat: Option<Repeat>,
+    trace: Option<Vec<Step<T>>>,
+}
+
+// Where a frequency was reached: pass is 1-based, line is the 0-based index of the change in the
+// input. The starting frequency is reached at pass 0, before any change is applied.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    pass: usize,
+    line: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.pass == 0 {
+            write!(f, "start")
+        } else {
+            write!(f, "pass {}, line {}", self.pass, self.line)
+        }
+    }
+}
+
+struct Repeat {
+    first_seen: Position,
+    repeated_at: Position,
+}
+
+struct Step<T> {
+    position: Position,
+    change: T,
+    frequency: T,
+}
+
+enum Status<T> {
+    Repeated,
+    EmptyInput,
+    // Every frequency reached in the first pass falls in a different residue class modulo the
+    // drift of one pass, so later passes only ever shift them further apart.
+    NeverRepeats { drift: T },
+}
+
+impl<T: fmt::Display> fmt::Display for Status<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Repeated => write!(f, "a frequency repeats"),
+            Status::EmptyInput => write!(f, "no frequency changes, nothing can repeat"),
+            Status::NeverRepeats { drift } => write!(
+                f,
+                "no frequency ever repeats: all first pass frequencies are distinct modulo the drift {}",
+                drift
+            ),
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let input = read_to_string("input.txt")?;
 
-    let Solution { part1, part2 } = process_frequencies(&input)?;
+    // --diagnostics: where the repeat happened. --trace <file>: frequency series as CSV.
+    // --width <32|64|128>: integer width of the frequencies.
+    let args = env::args().collect::<Vec<String>>();
+    let diagnostics = args.iter().any(|arg| arg == "--diagnostics");
+    let trace_file = option_value(&args, "--trace")?;
+
+    match option_value(&args, "--width")? {
+        None | Some("64") => solve::<i64>(&input, diagnostics, trace_file),
+        Some("32") => solve::<i32>(&input, diagnostics, trace_file),
+        Some("128") => solve::<i128>(&input, diagnostics, trace_file),
+        Some(width) => Err(format!("Unsupported width {}", width).into()),
+    }
+}
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => Ok(Some(
+            args.get(idx + 1)
+                .ok_or(format!("{} expects a value", name))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn solve<T: Frequency>(input: &str, diagnostics: bool, trace_file: Option<&str>) -> Result<()> {
+    let Solution {
+        part1,
+        part2,
+        status,
+        repeat,
+        trace,
+    } = process_frequencies::<T>(input, trace_file.is_some())?;
 
-    assert_eq!(423, part1.unwrap());
-    assert_eq!(61126, part2.unwrap());
+    assert_eq!(T::from(423), part1.unwrap());
     println!("Part1: {}", part1.unwrap());
-    println!("Part2: {}", part2.unwrap());
+    match part2 {
+        Some(part2) => {
+            assert_eq!(T::from(61126), part2);
+            println!("Part2: {}", part2);
+        }
+        None => println!("Part2: none, {}", status),
+    }
+
+    if diagnostics {
+        if let Some(Repeat {
+            first_seen,
+            repeated_at,
+        }) = repeat
+        {
+            println!("First seen at {}, repeated at {}", first_seen, repeated_at);
+        }
+    }
+    if let (Some(file), Some(trace)) = (trace_file, trace) {
+        write_trace(file, &trace)?;
+    }
     Ok(())
 }
 
-fn process_frequencies(input: &str) -> Result<Solution> {
-    let (mut current_frequency, mut part1, mut part2) = (0, None, None);
-    let mut reached_steps = HashSet::new();
-    reached_steps.insert(current_frequency);
+fn write_trace<T: Frequency>(file: &str, trace: &[Step<T>]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(file)?);
+
+    writeln!(writer, "step,pass,line,change,frequency")?;
+    for (step, entry) in trace.iter().enumerate() {
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            step, entry.position.pass, entry.position.line, entry.change, entry.frequency
+        )?;
+    }
+    writer.flush()?;
 
+    Ok(())
+}
+
+// After the first pass, every later frequency is a first pass frequency shifted by a multiple of
+// the drift. Two frequencies can only meet if they share a residue modulo the drift.
+fn can_repeat<T: Frequency>(reached_steps: &HashMap<T, Position>, drift: T) -> bool {
+    let zero = T::from(0);
+    if drift == zero {
+        return true;
+    }
+
+    // The starting 0 is never reached again by shifting forward, skip it. Had the first pass
+    // reached 0, it would already be the repeated frequency.
+    // The remainder only overflows for MIN modulo -1, where every residue is 0 anyway.
+    let mut residues = HashSet::new();
+    reached_steps
+        .keys()
+        .filter(|&&frequency| frequency != zero)
+        .any(|&frequency| !residues.insert(frequency.checked_rem_euclid(drift).unwrap_or(zero)))
+}
+
+fn process_frequencies<T: Frequency>(input: &str, record_trace: bool) -> Result<Solution<T>> {
+    let (mut current_frequency, mut part1, mut part2) = (T::from(0), None, None);
+    let mut repeat = None;
+    let mut trace = if record_trace { Some(Vec::new()) } else { None };
+
+    let start = Position { pass: 0, line: 0 };
+    let mut reached_steps = HashMap::new();
+    reached_steps.insert(current_frequency, start);
+    if let Some(trace) = trace.as_mut() {
+        trace.push(Step {
+            position: start,
+            change: T::from(0),
+            frequency: current_frequency,
+        });
+    }
+
+    if input.lines().next().is_none() {
+        return Ok(Solution {
+            part1: Some(current_frequency),
+            part2,
+            status: Status::EmptyInput,
+            repeat,
+            trace,
+        });
+    }
+
+    let mut pass = 0;
     while part1.is_none() || part2.is_none() {
-        for value in input.lines() {
-            current_frequency += value.parse::<i32>()?;
+        pass += 1;
+        for (line, value) in input.lines().enumerate() {
+            let position = Position { pass, line };
+            let change = T::parse(value)?;
+            current_frequency = current_frequency.checked_add(change).ok_or_else(|| {
+                format!(
+                    "Frequency overflow at {}: {} {:+}",
+                    position, current_frequency, change
+                )
+            })?;
+
+            if let Some(trace) = trace.as_mut() {
+                trace.push(Step {
+                    position,
+                    change,
+                    frequency: current_frequency,
+                });
+            }
 
             // Find first repeated frequency only
-            if part2.is_none() && reached_steps.contains(&current_frequency) {
-                part2 = Some(current_frequency);
-            } else {
-                reached_steps.insert(current_frequency);
+            if part2.is_none() {
+                if let Some(&first_seen) = reached_steps.get(&current_frequency) {
+                    part2 = Some(current_frequency);
+                    repeat = Some(Repeat {
+                        first_seen,
+                        repeated_at: position,
+                    });
+                } else {
+                    reached_steps.insert(current_frequency, position);
+                }
             }
         }
 
         // First reached frequency only
         if part1.is_none() {
             part1 = Some(current_frequency);
+
+            if part2.is_none() && !can_repeat(&reached_steps, current_frequency) {
+                return Ok(Solution {
+                    part1,
+                    part2,
+                    status: Status::NeverRepeats {
+                        drift: current_frequency,
+                    },
+                    repeat,
+                    trace,
+                });
+            }
         }
     }
 
-    Ok(Solution { part1, part2 })
+    Ok(Solution {
+        part1,
+        part2,
+        status: Status::Repeated,
+        repeat,
+        trace,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(input: &str) -> Solution<i64> {
+        process_frequencies(input, true).unwrap()
+    }
+
+    #[test]
+    fn never_repeats() {
+        let solution = solve("+1\n+1");
+
+        assert_eq!(solution.part1, Some(2));
+        assert_eq!(solution.part2, None);
+        assert!(matches!(solution.status, Status::NeverRepeats { drift: 2 }));
+    }
+
+    #[test]
+    fn repeat_positions() {
+        let solution = solve("+3\n-2");
+        assert_eq!(solution.part2, Some(3));
+
+        let repeat = solution.repeat.unwrap();
+        assert_eq!((repeat.first_seen.pass, repeat.first_seen.line), (1, 0));
+        assert_eq!((repeat.repeated_at.pass, repeat.repeated_at.line), (3, 1));
+
+        let trace = solution.trace.unwrap();
+        assert_eq!(
+            trace
+                .iter()
+                .map(|step| step.frequency)
+                .collect::<Vec<i64>>(),
+            [0, 3, 1, 4, 2, 5, 3]
+        );
+        assert_eq!(
+            trace.iter().map(|step| step.change).collect::<Vec<i64>>(),
+            [0, 3, -2, 3, -2, 3, -2]
+        );
+    }
+
+    #[test]
+    fn repeats_after_several_passes() {
+        let solution = solve("+7\n-3\n-2");
+
+        assert_eq!(solution.part1, Some(2));
+        assert_eq!(solution.part2, Some(4));
+        assert!(matches!(solution.status, Status::Repeated));
+    }
+
+    #[test]
+    fn repeats_the_start() {
+        let solution = solve("+1\n-1");
+        assert_eq!(solution.part2, Some(0));
+
+        let repeat = solution.repeat.unwrap();
+        assert_eq!(repeat.first_seen.pass, 0);
+        assert_eq!((repeat.repeated_at.pass, repeat.repeated_at.line), (1, 1));
+    }
+
+    #[test]
+    fn empty_input() {
+        let solution = solve("");
+
+        assert_eq!(solution.part1, Some(0));
+        assert_eq!(solution.part2, None);
+        assert!(matches!(solution.status, Status::EmptyInput));
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(process_frequencies::<i32>("+2147483647\n+1", false).is_err());
+        assert!(process_frequencies::<i64>("+2147483647\n+1", false).is_ok());
+    }
+
+    #[test]
+    fn trace_csv() {
+        let trace = solve("+1\n-2").trace.unwrap();
+        let file = env::temp_dir().join(format!("day1-trace-{}.csv", std::process::id()));
+        let file = file.to_str().unwrap();
+
+        write_trace(file, &trace).unwrap();
+        let csv = read_to_string(file).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "step,pass,line,change,frequency");
+        assert_eq!(lines[1], "0,0,0,0,0");
+        assert_eq!(lines[2], "1,1,0,1,1");
+        assert_eq!(lines[3], "2,1,1,-2,-1");
+    }
 }
@@ -1,36 +1,272 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs::read_to_string;
+use std::time::Instant;
 
 type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
-fn part1(input: &str) {
-    let (mut two_total, mut three_total) = (0, 0);
+fn part1(input: &str, kinds: &[usize]) {
+    let checksum = checksum(input, kinds);
+
+    if kinds == [2, 3] {
+        assert_eq!(checksum, 5368);
+    }
+    println!("Part1: {}", checksum);
+}
+
+// Multiply, for each N in kinds, the number of IDs containing some letter exactly N times.
+fn checksum(input: &str, kinds: &[usize]) -> usize {
+    let mut totals = vec![0; kinds.len()];
     for value in input.lines() {
-        let mut occurences = [0; 256];
-        value.chars().for_each(|c| occurences[c as usize] += 1);
+        let mut occurences: HashMap<char, usize> = HashMap::new();
+        value
+            .chars()
+            .for_each(|c| *occurences.entry(c).or_default() += 1);
+
+        for (total, kind) in totals.iter_mut().zip(kinds) {
+            if occurences.values().any(|count| count == kind) {
+                *total += 1;
+            }
+        }
+    }
+
+    totals.iter().product()
+}
+
+fn part2(input: &str) -> Result<()> {
+    let ids = input.lines().collect::<Vec<&str>>();
+    let (value, value2) = find_near_duplicate(&ids).ok_or("No IDs differ by exactly one letter")?;
+
+    let common = common_letters(value, value2);
+    assert_eq!(common, "cvgywxqubnuaefmsljdrpfzyi".to_string());
+    println!("Part2: {}", common);
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Metric {
+    // Number of differing letters, between IDs of the same length only.
+    Hamming,
+    // Number of letter insertions, deletions and substitutions.
+    Levenshtein,
+}
 
-        if occurences.iter().any(|&count| count == 2) {
-            two_total += 1;
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Metric::Hamming => write!(f, "Hamming"),
+            Metric::Levenshtein => write!(f, "Levenshtein"),
         }
-        if occurences.iter().any(|&count| count == 3) {
-            three_total += 1;
+    }
+}
+
+struct NearDuplicate<'a> {
+    first: &'a str,
+    second: &'a str,
+    // Positions of the letters of the first ID that are edited to get the second one. For an
+    // insertion, the position of the letter it's inserted before.
+    positions: Vec<usize>,
+}
+
+// Every pair of IDs at exactly `distance` from each other, O(n² · L) for Hamming and
+// O(n² · L²) for Levenshtein.
+fn near_duplicates<'a>(
+    ids: &[&'a str],
+    distance: usize,
+    metric: Metric,
+) -> Result<Vec<NearDuplicate<'a>>> {
+    let mut pairs = Vec::new();
+    for (idx, first) in ids.iter().enumerate() {
+        for second in &ids[idx + 1..] {
+            let positions = match metric {
+                Metric::Hamming => hamming_positions(first, second),
+                Metric::Levenshtein => levenshtein_positions(first, second, distance),
+            };
+
+            if let Some(positions) = positions.filter(|positions| positions.len() == distance) {
+                pairs.push(NearDuplicate {
+                    first,
+                    second,
+                    positions,
+                });
+            }
         }
     }
 
-    assert_eq!(two_total * three_total, 5368);
-    println!("Part1: {}", two_total * three_total);
+    if pairs.is_empty() {
+        return Err(format!("No pair of IDs at {} distance {}", metric, distance).into());
+    }
+
+    Ok(pairs)
 }
 
-fn part2(input: &str) {
-    for value in input.lines() {
-        for value2 in input.lines() {
-            if count_differences(value, value2) == 1 {
-                let common = common_letters(value, value2);
-                assert_eq!(common, "cvgywxqubnuaefmsljdrpfzyi".to_string());
-                println!("Part2: {}", common);
-                return;
+fn hamming_positions(str1: &str, str2: &str) -> Option<Vec<usize>> {
+    if str1.chars().count() != str2.chars().count() {
+        return None;
+    }
+
+    Some(
+        str1.chars()
+            .zip(str2.chars())
+            .enumerate()
+            .filter(|(_, (c1, c2))| c1 != c2)
+            .map(|(position, _)| position)
+            .collect(),
+    )
+}
+
+// Edit positions of a shortest edit script, None if it's longer than max_distance.
+fn levenshtein_positions(str1: &str, str2: &str, max_distance: usize) -> Option<Vec<usize>> {
+    let letters1 = str1.chars().collect::<Vec<char>>();
+    let letters2 = str2.chars().collect::<Vec<char>>();
+    let (length1, length2) = (letters1.len(), letters2.len());
+    if length1.max(length2) - length1.min(length2) > max_distance {
+        return None;
+    }
+
+    // distances[i][j] is the distance between the first i letters of str1 and first j of str2.
+    let mut distances = vec![vec![0usize; length2 + 1]; length1 + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=length2).collect();
+    for i in 1..=length1 {
+        for j in 1..=length2 {
+            let substitution = if letters1[i - 1] == letters2[j - 1] {
+                0
+            } else {
+                1
+            };
+            distances[i][j] = (distances[i - 1][j - 1] + substitution)
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+        }
+    }
+    if distances[length1][length2] > max_distance {
+        return None;
+    }
+
+    // Walk back from the bottom-right corner, recording where edits happened.
+    let mut positions = Vec::new();
+    let (mut i, mut j) = (length1, length2);
+    while i > 0 || j > 0 {
+        if i > 0
+            && j > 0
+            && letters1[i - 1] == letters2[j - 1]
+            && distances[i][j] == distances[i - 1][j - 1]
+        {
+            i -= 1;
+            j -= 1;
+        } else if i > 0 && j > 0 && distances[i][j] == distances[i - 1][j - 1] + 1 {
+            // Substitution
+            i -= 1;
+            j -= 1;
+            positions.push(i);
+        } else if i > 0 && distances[i][j] == distances[i - 1][j] + 1 {
+            // Deletion
+            i -= 1;
+            positions.push(i);
+        } else {
+            // Insertion
+            j -= 1;
+            positions.push(i);
+        }
+    }
+    positions.reverse();
+
+    Some(positions)
+}
+
+fn print_near_duplicates(input: &str, distance: usize, metric: Metric) -> Result<()> {
+    let ids = input.lines().collect::<Vec<&str>>();
+
+    for pair in near_duplicates(&ids, distance, metric)? {
+        println!(
+            "{} {} at positions {:?}",
+            pair.first, pair.second, pair.positions
+        );
+    }
+
+    Ok(())
+}
+
+// Compare every pair of IDs of the same length, O(n² · L).
+fn find_near_duplicate_naive<'a>(ids: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    let lengths = ids
+        .iter()
+        .map(|id| id.chars().count())
+        .collect::<Vec<usize>>();
+    for (idx, value) in ids.iter().enumerate() {
+        for (idx2, value2) in ids.iter().enumerate().skip(idx + 1) {
+            if lengths[idx] == lengths[idx2] && count_differences(value, value2) == 1 {
+                return Some((value, value2));
+            }
+        }
+    }
+
+    None
+}
+
+// Two IDs differ by exactly one letter at position i iff they have the same length, the same
+// letters before i and the same letters after i. For every position i, key each ID by (length,
+// hash of the prefix, hash of the suffix), using rolling hashes computed once per ID. That's
+// O(n · L) overall, hash collisions are ruled out by comparing the IDs.
+fn find_near_duplicate<'a>(ids: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    let hashes = ids.iter().map(|id| rolling_hashes(id)).collect::<Vec<_>>();
+    let max_length = hashes
+        .iter()
+        .map(|(prefixes, _)| prefixes.len() - 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut seen: HashMap<(usize, u64, u64), Vec<usize>> = HashMap::with_capacity(ids.len());
+    for position in 0..max_length {
+        seen.clear();
+        for (idx, (prefixes, suffixes)) in hashes.iter().enumerate() {
+            let length = prefixes.len() - 1;
+            if position >= length {
+                continue;
             }
+
+            let key = (length, prefixes[position], suffixes[position + 1]);
+            let candidates = seen.entry(key).or_default();
+            // Identical IDs share every key, only keep the first one.
+            if candidates.iter().any(|&other| ids[other] == ids[idx]) {
+                continue;
+            }
+            if let Some(&other) = candidates
+                .iter()
+                .find(|&&other| count_differences(ids[other], ids[idx]) == 1)
+            {
+                return Some((ids[other], ids[idx]));
+            }
+            candidates.push(idx);
         }
     }
+
+    None
+}
+
+const HASH_BASE: u64 = 0x100_0000_01b3;
+
+// prefixes[i] hashes the letters before i, suffixes[i] hashes the letters from i onwards.
+fn rolling_hashes(id: &str) -> (Vec<u64>, Vec<u64>) {
+    let letters = id.chars().map(|c| c as u64).collect::<Vec<u64>>();
+    let length = letters.len();
+
+    let mut prefixes = vec![0u64; length + 1];
+    let mut suffixes = vec![0u64; length + 1];
+    for i in 0..length {
+        prefixes[i + 1] = prefixes[i].wrapping_mul(HASH_BASE).wrapping_add(letters[i]);
+        let j = length - 1 - i;
+        suffixes[j] = suffixes[j + 1]
+            .wrapping_mul(HASH_BASE)
+            .wrapping_add(letters[j]);
+    }
+
+    (prefixes, suffixes)
 }
 
 fn count_differences(str1: &str, str2: &str) -> usize {
@@ -48,11 +284,202 @@ fn common_letters(str1: &str, str2: &str) -> String {
         .collect()
 }
 
+// Generate `count` random lowercase IDs, where only the last two differ by one letter.
+fn generate_ids(count: usize, length: usize) -> Vec<String> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next_letter = || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (b'a' + (state % 26) as u8) as char
+    };
+
+    let mut ids = (1..count)
+        .map(|_| (0..length).map(|_| next_letter()).collect::<String>())
+        .collect::<Vec<String>>();
+
+    let mut twin = ids[count - 2].chars().collect::<Vec<char>>();
+    twin[length / 2] = if twin[length / 2] == 'z' { 'a' } else { 'z' };
+    ids.push(twin.into_iter().collect());
+
+    ids
+}
+
+fn bench(count: usize) {
+    let ids = generate_ids(count, 26);
+    let ids = ids.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let start = Instant::now();
+    let found = find_near_duplicate(&ids);
+    println!("Hashed: {} IDs in {:?}", count, start.elapsed());
+
+    // The quadratic search gets out of hand quickly, only time it on a smaller input.
+    let naive_count = count.min(10_000);
+    let start = Instant::now();
+    let naive_found = find_near_duplicate_naive(&ids[..naive_count]);
+    println!("Naive: {} IDs in {:?}", naive_count, start.elapsed());
+
+    assert!(found.is_some());
+    if naive_count == count {
+        assert_eq!(found, naive_found);
+    }
+}
+
 fn main() -> Result<()> {
+    // --bench <count>: time the near-duplicate search on generated IDs.
+    // --kinds <N,M,...>: letter counts the checksum looks for, 2,3 by default.
+    // --pairs [--distance <k>] [--metric <hamming|levenshtein>]: list every pair of IDs at
+    // distance k, 1 by default.
+    let args = env::args().collect::<Vec<String>>();
+    if args.iter().any(|arg| arg == "--bench") {
+        let count = match args
+            .iter()
+            .position(|arg| arg == "--bench")
+            .and_then(|idx| args.get(idx + 1))
+        {
+            Some(count) => count.parse()?,
+            None => 100_000,
+        };
+        if count < 2 {
+            return Err("Benchmark needs at least 2 IDs".into());
+        }
+        bench(count);
+        return Ok(());
+    }
+
+    let kinds = match option_value(&args, "--kinds")? {
+        Some(kinds) => kinds
+            .split(',')
+            .map(|kind| kind.parse())
+            .collect::<::std::result::Result<Vec<usize>, _>>()?,
+        None => vec![2, 3],
+    };
+
     let input = read_to_string("input.txt")?;
 
-    part1(&input);
-    part2(&input);
+    if args.iter().any(|arg| arg == "--pairs") {
+        let distance = match option_value(&args, "--distance")? {
+            Some(distance) => distance.parse()?,
+            None => 1,
+        };
+        let metric = match option_value(&args, "--metric")? {
+            None | Some("hamming") => Metric::Hamming,
+            Some("levenshtein") => Metric::Levenshtein,
+            Some(metric) => return Err(format!("Unknown metric {}", metric).into()),
+        };
+
+        return print_near_duplicates(&input, distance, metric);
+    }
+
+    part1(&input, &kinds);
+    part2(&input)?;
 
     Ok(())
 }
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => Ok(Some(
+            args.get(idx + 1)
+                .ok_or(format!("{} expects a value", name))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same_near_duplicate(ids: &[&str], expected: Option<(&str, &str)>) {
+        assert_eq!(find_near_duplicate(ids), expected);
+        assert_eq!(find_near_duplicate_naive(ids), expected);
+    }
+
+    #[test]
+    fn generated_ids() {
+        for &count in &[2, 3, 100, 500] {
+            let ids = generate_ids(count, 26);
+            let ids = ids.iter().map(String::as_str).collect::<Vec<&str>>();
+
+            let expected = Some((ids[count - 2], ids[count - 1]));
+            assert_same_near_duplicate(&ids, expected);
+        }
+    }
+
+    #[test]
+    fn different_lengths() {
+        assert_same_near_duplicate(&["abc", "abcd", "ab", "abx"], Some(("abc", "abx")));
+        assert_same_near_duplicate(&["ax", "abc", "abz"], Some(("abc", "abz")));
+        assert_same_near_duplicate(&["abc", "abcd", "ab", "xbcd", "a"], Some(("abcd", "xbcd")));
+        assert_same_near_duplicate(&["abc", "abcd", "ab", ""], None);
+    }
+
+    #[test]
+    fn non_ascii_ids() {
+        assert_same_near_duplicate(&["hallo", "héllo"], Some(("hallo", "héllo")));
+        // Same number of letters, not of bytes.
+        assert_same_near_duplicate(&["ae", "aé"], Some(("ae", "aé")));
+        assert_same_near_duplicate(&["中国語", "日本語", "日本人"], Some(("日本語", "日本人")));
+        assert_same_near_duplicate(&["日本", "本日"], None);
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        assert_same_near_duplicate(&["abc", "abc", "abd"], Some(("abc", "abd")));
+
+        let mut ids = vec!["abcdef"; 100_000];
+        ids.push("zzzzzz");
+        assert_eq!(find_near_duplicate(&ids), None);
+    }
+
+    #[test]
+    fn levenshtein_edit_positions() {
+        // Substitution, deletion, insertion.
+        assert_eq!(levenshtein_positions("abc", "axc", 1), Some(vec![1]));
+        assert_eq!(levenshtein_positions("abcd", "abd", 1), Some(vec![2]));
+        assert_eq!(levenshtein_positions("abc", "abxc", 1), Some(vec![2]));
+        assert_eq!(levenshtein_positions("abc", "abcx", 1), Some(vec![3]));
+        assert_eq!(levenshtein_positions("abc", "xabc", 1), Some(vec![0]));
+
+        assert_eq!(levenshtein_positions("abc", "abc", 1), Some(vec![]));
+        assert_eq!(levenshtein_positions("abcd", "xbd", 2), Some(vec![0, 2]));
+        assert_eq!(levenshtein_positions("abcd", "xbd", 1), None);
+        assert_eq!(levenshtein_positions("abcdef", "ab", 3), None);
+        assert_eq!(levenshtein_positions("日本語", "日語", 1), Some(vec![1]));
+    }
+
+    #[test]
+    fn near_duplicate_pairs() {
+        let ids = ["abcd", "abd", "abxd", "wxyz"];
+
+        let pairs = near_duplicates(&ids, 1, Metric::Levenshtein).unwrap();
+        assert_eq!(
+            pairs
+                .iter()
+                .map(|pair| (pair.first, pair.second, pair.positions.clone()))
+                .collect::<Vec<_>>(),
+            [
+                ("abcd", "abd", vec![2]),
+                ("abcd", "abxd", vec![2]),
+                ("abd", "abxd", vec![2]),
+            ]
+        );
+
+        let pairs = near_duplicates(&ids, 1, Metric::Hamming).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].first, pairs[0].second), ("abcd", "abxd"));
+    }
+
+    #[test]
+    fn no_near_duplicate_pairs() {
+        let error = near_duplicates(&["abc", "xyz"], 1, Metric::Hamming)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "No pair of IDs at Hamming distance 1");
+
+        assert!(near_duplicates(&["abc", "abc"], 1, Metric::Levenshtein).is_err());
+        assert!(near_duplicates(&[], 1, Metric::Levenshtein).is_err());
+    }
+}
@@ -1,23 +1,214 @@
-#[macro_use]
-extern crate more_asserts;
-
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::fs::read_to_string;
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{read_to_string, File};
+use std::io::{BufWriter, Write};
+use std::num::ParseIntError;
+use std::str::FromStr;
 
 type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
-type Fabric = [[u32; 1000]; 1000];
+enum Algorithm {
+    // Mark every square inch of every claim on the fabric.
+    Grid,
+    // Sweep the claims' rectangles from left to right, without materializing the fabric.
+    Sweep,
+}
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => Ok(Some(
+            args.get(idx + 1)
+                .ok_or(format!("{} expects a value", name))?,
+        )),
+        None => Ok(None),
+    }
+}
 
 fn main() -> Result<()> {
+    // --algorithm <grid|sweep>: how overlaps are computed, grid by default.
+    // --overlaps: print every claim's overlaps and the groups of overlapping claims.
+    // --covers <x,y>: print the claims covering a square inch.
+    // --lenient: report malformed claims and solve over the valid ones instead of failing.
+    // --ascii: draw the fabric with '#' for claimed and 'X' for overlapping square inches.
+    // --heatmap <file>: render the overlap counts to a PPM image, the intact claim outlined.
+    let args = env::args().collect::<Vec<String>>();
+    let algorithm = match option_value(&args, "--algorithm")? {
+        None | Some("grid") => Algorithm::Grid,
+        Some("sweep") => Algorithm::Sweep,
+        Some(algorithm) => return Err(format!("Unknown algorithm {}", algorithm).into()),
+    };
+
     let input = read_to_string("input.txt")?;
 
-    let marked_fabric = part1(&input);
-    part2(&input, &marked_fabric);
+    let claims = if args.iter().any(|arg| arg == "--lenient") {
+        let (claims, errors) = parse_claims_lenient(&input);
+        for error in errors {
+            eprintln!("Skipping {}", error);
+        }
+        claims
+    } else {
+        parse_claims(&input)?
+    };
+    match algorithm {
+        Algorithm::Grid => {
+            let marked_fabric = part1(&claims);
+            part2(&claims, &marked_fabric);
+        }
+        Algorithm::Sweep => {
+            let overlaps = sweep_overlap_area(&claims);
+            assert_eq!(overlaps, 104712);
+            println!("Part1: {}", overlaps);
+
+            let graph = OverlapGraph::new(&claims);
+            for claim in graph.intact().map(|idx| &claims[idx]) {
+                assert_eq!(840, claim.id);
+                println!("Part2: {}", claim.id);
+            }
+        }
+    }
+
+    if args.iter().any(|arg| arg == "--overlaps") {
+        print_overlaps(&claims);
+    }
+    if let Some(square) = option_value(&args, "--covers")? {
+        let mut coordinates = square.split(',');
+        let (x, y) = match (coordinates.next(), coordinates.next(), coordinates.next()) {
+            (Some(x), Some(y), None) => (x.parse()?, y.parse()?),
+            _ => return Err(format!("Invalid square {}, expected x,y", square).into()),
+        };
+
+        let ids = covering(&claims, x, y)
+            .map(|claim| claim.id.to_string())
+            .collect::<Vec<String>>();
+        println!("{},{} covered by [{}]", x, y, ids.join(", "));
+    }
+
+    let heatmap = option_value(&args, "--heatmap")?;
+    if args.iter().any(|arg| arg == "--ascii") || heatmap.is_some() {
+        let fabric = Fabric::marked(&claims);
+
+        if args.iter().any(|arg| arg == "--ascii") {
+            print!("{}", render_ascii(&fabric)?);
+        }
+        if let Some(file) = heatmap {
+            let intact = claims
+                .iter()
+                .filter(|claim| check_claim(&fabric, claim))
+                .collect::<Vec<&Claim>>();
+            write_heatmap(&fabric, &intact, file)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Widest and tallest fabric that still reads fine in a terminal.
+const ASCII_MAX_LENGTH: usize = 200;
+
+// Like the puzzle's illustrations, from the top-left corner of the whole fabric.
+fn render_ascii(fabric: &Fabric) -> Result<String> {
+    let (x1, y1) = (fabric.x0 + fabric.length, fabric.y0 + fabric.height);
+    if x1 > ASCII_MAX_LENGTH || y1 > ASCII_MAX_LENGTH {
+        return Err(format!(
+            "Fabric is {}x{} inches, too large for ASCII rendering",
+            x1, y1
+        )
+        .into());
+    }
+
+    let mut rendered = String::with_capacity((x1 + 1) * y1);
+    for y in 0..y1 {
+        for x in 0..x1 {
+            rendered.push(match fabric.get(x, y) {
+                0 => '.',
+                1 => '#',
+                _ => 'X',
+            });
+        }
+        rendered.push('\n');
+    }
+
+    Ok(rendered)
+}
+
+// Unclaimed square inches are black, claimed once dark blue, overlapping ones go from yellow to
+// red as more claims pile up.
+fn heat_colour(count: u32, max_count: u32) -> [u8; 3] {
+    match count {
+        0 => [0, 0, 0],
+        1 => [40, 40, 120],
+        _ => {
+            let heat = f64::from(count - 2) / f64::from(max_count.max(3) - 2);
+            [255, (255.0 * (1.0 - heat)) as u8, 0]
+        }
+    }
+}
+
+const OUTLINE_COLOUR: [u8; 3] = [0, 255, 0];
+
+// Binary PPM of the fabric's bounding box, one pixel per square inch.
+fn write_heatmap(fabric: &Fabric, outlined: &[&Claim], file: &str) -> Result<()> {
+    let max_count = fabric.squares.iter().copied().max().unwrap_or(0);
+    let mut pixels = vec![[0u8; 3]; fabric.length * fabric.height];
+    for x in 0..fabric.length {
+        for y in 0..fabric.height {
+            let count = fabric.get(fabric.x0 + x, fabric.y0 + y);
+            pixels[y * fabric.length + x] = heat_colour(count, max_count);
+        }
+    }
+
+    for claim in outlined {
+        if claim.length == 0 || claim.height == 0 {
+            continue;
+        }
+
+        let (left, top) = (claim.x - fabric.x0, claim.y - fabric.y0);
+        let (right, bottom) = (left + claim.length - 1, top + claim.height - 1);
+        for x in left..=right {
+            pixels[top * fabric.length + x] = OUTLINE_COLOUR;
+            pixels[bottom * fabric.length + x] = OUTLINE_COLOUR;
+        }
+        for y in top..=bottom {
+            pixels[y * fabric.length + left] = OUTLINE_COLOUR;
+            pixels[y * fabric.length + right] = OUTLINE_COLOUR;
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(file)?);
+    write!(writer, "P6\n{} {}\n255\n", fabric.length, fabric.height)?;
+    for pixel in pixels {
+        writer.write_all(&pixel)?;
+    }
+    writer.flush()?;
 
     Ok(())
 }
 
+fn print_overlaps(claims: &[Claim]) {
+    let graph = OverlapGraph::new(claims);
+
+    for (idx, claim) in claims.iter().enumerate() {
+        let overlaps = graph
+            .overlapping(idx)
+            .iter()
+            .map(|&(other, area)| format!("#{} ({})", claims[other].id, area))
+            .collect::<Vec<String>>();
+        println!("#{} overlaps [{}]", claim.id, overlaps.join(", "));
+    }
+
+    for component in graph.components() {
+        let ids = component
+            .iter()
+            .map(|&idx| format!("#{}", claims[idx].id))
+            .collect::<Vec<String>>();
+        println!("Group of {}: [{}]", component.len(), ids.join(", "));
+    }
+}
+
 struct Claim {
     id: usize,
     x: usize,
@@ -26,8 +217,76 @@ struct Claim {
     height: usize,
 }
 
-impl Claim {
-    fn new(claim: &str) -> Self {
+#[derive(Debug)]
+enum ClaimError {
+    // The line isn't of the form "#id @ x,y: lengthxheight".
+    Format(String),
+    // A field is too large to be parsed.
+    Field {
+        name: &'static str,
+        value: String,
+        source: ParseIntError,
+    },
+    // The claim reaches past the largest coordinate.
+    Extent(String),
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClaimError::Format(claim) => write!(
+                f,
+                "malformed claim \"{}\", expected \"#id @ x,y: lengthxheight\"",
+                claim
+            ),
+            ClaimError::Field {
+                name,
+                value,
+                source,
+            } => write!(f, "invalid claim {} \"{}\": {}", name, value, source),
+            ClaimError::Extent(claim) => write!(f, "claim \"{}\" extends out of range", claim),
+        }
+    }
+}
+
+impl Error for ClaimError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClaimError::Format(_) | ClaimError::Extent(_) => None,
+            ClaimError::Field { source, .. } => Some(source),
+        }
+    }
+}
+
+// A claim that failed to parse, with its 1-based line number.
+struct LineError {
+    line: usize,
+    error: ClaimError,
+}
+
+// Returned from main, which prints errors with Debug.
+impl fmt::Debug for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl Error for LineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl FromStr for Claim {
+    type Err = ClaimError;
+
+    fn from_str(claim: &str) -> ::std::result::Result<Self, Self::Err> {
         lazy_static! {
             static ref CLAIM_REGEX: Regex = Regex::new(
                 r"^#(?P<id>\d+) @ (?P<x>\d+),(?P<y>\d+): (?P<length>\d+)x(?P<height>\d+)$"
@@ -35,39 +294,160 @@ impl Claim {
             .unwrap();
         };
 
-        let parsed = CLAIM_REGEX.captures(claim).unwrap();
-        Claim {
-            id: parsed["id"].parse().unwrap(),
-            x: parsed["x"].parse().unwrap(),
-            y: parsed["y"].parse().unwrap(),
-            length: parsed["length"].parse().unwrap(),
-            height: parsed["height"].parse().unwrap(),
+        let parsed = CLAIM_REGEX
+            .captures(claim)
+            .ok_or_else(|| ClaimError::Format(claim.to_string()))?;
+        let field = |name: &'static str| {
+            parsed[name].parse().map_err(|source| ClaimError::Field {
+                name,
+                value: parsed[name].to_string(),
+                source,
+            })
+        };
+
+        let parsed = Claim {
+            id: field("id")?,
+            x: field("x")?,
+            y: field("y")?,
+            length: field("length")?,
+            height: field("height")?,
+        };
+        // Past this, a claim's far edge is always in range.
+        if parsed.x.checked_add(parsed.length).is_none()
+            || parsed.y.checked_add(parsed.height).is_none()
+        {
+            return Err(ClaimError::Extent(claim.to_string()));
         }
+
+        Ok(parsed)
     }
 }
 
-fn mark_fabric(fabric: &mut Fabric, line: &str) {
-    let claim = Claim::new(line);
-    assert_le!(claim.x + claim.length, 1000);
-    assert_le!(claim.y + claim.height, 1000);
+// Fail on the first malformed claim.
+fn parse_claims(input: &str) -> ::std::result::Result<Vec<Claim>, LineError> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| {
+            line.parse().map_err(|error| LineError {
+                line: idx + 1,
+                error,
+            })
+        })
+        .collect()
+}
 
-    for x in (claim.x)..(claim.x + claim.length) {
-        for y in (claim.y)..(claim.y + claim.height) {
-            fabric[x][y] += 1;
+// Keep the valid claims, and every malformed one's error.
+fn parse_claims_lenient(input: &str) -> (Vec<Claim>, Vec<LineError>) {
+    let (mut claims, mut errors) = (Vec::new(), Vec::new());
+    for (idx, line) in input.lines().enumerate() {
+        match line.parse() {
+            Ok(claim) => claims.push(claim),
+            Err(error) => errors.push(LineError {
+                line: idx + 1,
+                error,
+            }),
         }
     }
+
+    (claims, errors)
 }
 
-fn part1(input: &str) -> [[u32; 1000]; 1000] {
-    let mut fabric = [[0u32; 1000]; 1000];
-    for line in input.lines() {
-        mark_fabric(&mut fabric, line);
+impl Claim {
+    // Square inches shared by both claims.
+    fn overlap_area(&self, other: &Claim) -> usize {
+        let x_overlap = (self.x + self.length)
+            .min(other.x + other.length)
+            .saturating_sub(self.x.max(other.x));
+        let y_overlap = (self.y + self.height)
+            .min(other.y + other.height)
+            .saturating_sub(self.y.max(other.y));
+
+        x_overlap * y_overlap
     }
 
-    let overlaps = fabric
-        .iter()
-        .map(|row| row.iter().filter(|&&s| s > 1).count())
-        .sum::<usize>();
+    fn covers(&self, x: usize, y: usize) -> bool {
+        self.x <= x && x < self.x + self.length && self.y <= y && y < self.y + self.height
+    }
+}
+
+// Square inches covered by the claims' bounding box, heap allocated. Square (x, y) is stored at
+// index (x - x0) * height + (y - y0).
+struct Fabric {
+    x0: usize,
+    y0: usize,
+    length: usize,
+    height: usize,
+    squares: Vec<u32>,
+}
+
+impl Fabric {
+    fn new(claims: &[Claim]) -> Self {
+        let x0 = claims.iter().map(|claim| claim.x).min().unwrap_or(0);
+        let y0 = claims.iter().map(|claim| claim.y).min().unwrap_or(0);
+        let x1 = claims
+            .iter()
+            .map(|claim| claim.x + claim.length)
+            .max()
+            .unwrap_or(0);
+        let y1 = claims
+            .iter()
+            .map(|claim| claim.y + claim.height)
+            .max()
+            .unwrap_or(0);
+
+        let (length, height) = (x1.max(x0) - x0, y1.max(y0) - y0);
+        Fabric {
+            x0,
+            y0,
+            length,
+            height,
+            squares: vec![0; length * height],
+        }
+    }
+
+    fn marked(claims: &[Claim]) -> Self {
+        let mut fabric = Fabric::new(claims);
+        for claim in claims {
+            fabric.mark(claim);
+        }
+
+        fabric
+    }
+
+    // Claims on square (x, y), 0 outside of the bounding box.
+    fn get(&self, x: usize, y: usize) -> u32 {
+        if x < self.x0 || y < self.y0 || x >= self.x0 + self.length || y >= self.y0 + self.height {
+            return 0;
+        }
+
+        self.squares[(x - self.x0) * self.height + y - self.y0]
+    }
+
+    // Squares of a claim, one slice per column.
+    fn columns<'a>(&'a self, claim: &Claim) -> impl Iterator<Item = &'a [u32]> {
+        let (y, height) = (claim.y - self.y0, claim.height);
+        (claim.x - self.x0..claim.x - self.x0 + claim.length).map(move |x| {
+            let start = x * self.height + y;
+            &self.squares[start..start + height]
+        })
+    }
+
+    fn mark(&mut self, claim: &Claim) {
+        let (y, height) = (claim.y - self.y0, claim.height);
+        for x in claim.x - self.x0..claim.x - self.x0 + claim.length {
+            let start = x * self.height + y;
+            self.squares[start..start + height]
+                .iter_mut()
+                .for_each(|square| *square += 1);
+        }
+    }
+}
+
+fn part1(claims: &[Claim]) -> Fabric {
+    let fabric = Fabric::marked(claims);
+
+    let overlaps = fabric.squares.iter().filter(|&&s| s > 1).count();
     assert_eq!(overlaps, 104712);
     println!("Part1: {}", overlaps);
 
@@ -75,23 +455,272 @@ fn part1(input: &str) -> [[u32; 1000]; 1000] {
 }
 
 fn check_claim(fabric: &Fabric, claim: &Claim) -> bool {
-    for x in (claim.x)..(claim.x + claim.length) {
-        for y in (claim.y)..(claim.y + claim.height) {
-            if fabric[x][y] > 1 {
-                return false;
+    fabric
+        .columns(claim)
+        .all(|column| column.iter().all(|&square| square <= 1))
+}
+
+fn part2(claims: &[Claim], fabric: &Fabric) {
+    for claim in claims {
+        if check_claim(fabric, claim) {
+            assert_eq!(840, claim.id);
+            println!("Part2: {}", claim.id);
+        }
+    }
+}
+
+// Area covered by at least two claims. The x coordinates where claims start or end split the
+// fabric into vertical slabs crossed by the same claims. In each slab, sweep the claims' y
+// intervals from top to bottom to get the length covered twice or more. That's O(n² log n) in the
+// number of claims, whatever their size.
+fn sweep_overlap_area(claims: &[Claim]) -> usize {
+    let mut xs = claims
+        .iter()
+        .flat_map(|claim| vec![claim.x, claim.x + claim.length])
+        .collect::<Vec<usize>>();
+    xs.sort_unstable();
+    xs.dedup();
+
+    let mut area = 0;
+    for slab in xs.windows(2) {
+        let (start, end) = (slab[0], slab[1]);
+
+        // Claim starts count as +1, ends as -1. Sorting puts ends before starts on the same y.
+        let mut events = claims
+            .iter()
+            .filter(|claim| claim.x <= start && end <= claim.x + claim.length)
+            .flat_map(|claim| vec![(claim.y, 1), (claim.y + claim.height, -1)])
+            .collect::<Vec<(usize, i32)>>();
+        events.sort_unstable();
+
+        let (mut covering, mut previous_y, mut overlapping) = (0, 0, 0);
+        for (y, delta) in events {
+            if covering > 1 {
+                overlapping += y - previous_y;
             }
+            covering += delta;
+            previous_y = y;
         }
+
+        area += overlapping * (end - start);
     }
 
-    true
+    area
 }
 
-fn part2(input: &str, fabric: &Fabric) {
-    for line in input.lines() {
-        let claim = Claim::new(line);
-        if check_claim(fabric, &claim) {
-            assert_eq!(840, claim.id);
-            println!("Part2: {}", claim.id);
+// Claims as nodes, with an edge between every two claims that overlap, weighted by the
+// overlapping area. Claims are referred to by their index in the claims slice.
+struct OverlapGraph {
+    edges: Vec<Vec<(usize, usize)>>,
+}
+
+impl OverlapGraph {
+    // Compare the claims pairwise, O(n²).
+    fn new(claims: &[Claim]) -> Self {
+        let mut edges = vec![Vec::new(); claims.len()];
+        for (idx, claim) in claims.iter().enumerate() {
+            for (other, other_claim) in claims.iter().enumerate().skip(idx + 1) {
+                let area = claim.overlap_area(other_claim);
+                if area > 0 {
+                    edges[idx].push((other, area));
+                    edges[other].push((idx, area));
+                }
+            }
+        }
+
+        OverlapGraph { edges }
+    }
+
+    // Claims overlapping a claim, with the area they share.
+    fn overlapping(&self, claim: usize) -> &[(usize, usize)] {
+        &self.edges[claim]
+    }
+
+    // Claims that don't overlap any other.
+    fn intact(&self) -> impl Iterator<Item = usize> + '_ {
+        self.edges
+            .iter()
+            .enumerate()
+            .filter(|(_, overlaps)| overlaps.is_empty())
+            .map(|(idx, _)| idx)
+    }
+
+    // Groups of claims connected through overlaps, each sorted by claim index.
+    fn components(&self) -> Vec<Vec<usize>> {
+        let mut visited = vec![false; self.edges.len()];
+        let mut components = Vec::new();
+
+        for start in 0..self.edges.len() {
+            if visited[start] {
+                continue;
+            }
+
+            visited[start] = true;
+            let mut component = Vec::new();
+            let mut queue = VecDeque::new();
+            queue.push_back(start);
+            while let Some(claim) = queue.pop_front() {
+                component.push(claim);
+                for &(other, _) in &self.edges[claim] {
+                    if !visited[other] {
+                        visited[other] = true;
+                        queue.push_back(other);
+                    }
+                }
+            }
+
+            component.sort_unstable();
+            components.push(component);
+        }
+
+        components
+    }
+}
+
+fn covering(claims: &[Claim], x: usize, y: usize) -> impl Iterator<Item = &Claim> {
+    claims.iter().filter(move |claim| claim.covers(x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_overlap_area(claims: &[Claim]) -> usize {
+        Fabric::marked(claims)
+            .squares
+            .iter()
+            .filter(|&&square| square > 1)
+            .count()
+    }
+
+    fn assert_overlap_area(input: &str, expected: usize) {
+        let claims = parse_claims(input).unwrap();
+        assert_eq!(grid_overlap_area(&claims), expected);
+        assert_eq!(sweep_overlap_area(&claims), expected);
+    }
+
+    #[test]
+    fn sample() {
+        assert_overlap_area("#1 @ 1,3: 4x4\n#2 @ 3,1: 4x4\n#3 @ 5,5: 2x2", 4);
+    }
+
+    #[test]
+    fn touching_edges() {
+        assert_overlap_area("#1 @ 0,0: 2x2\n#2 @ 2,0: 2x2\n#3 @ 0,2: 4x1", 0);
+        assert_overlap_area("#1 @ 0,0: 2x2\n#2 @ 1,1: 2x2\n#3 @ 2,2: 2x2", 2);
+    }
+
+    #[test]
+    fn nested_claims() {
+        assert_overlap_area("#1 @ 0,0: 5x5\n#2 @ 1,1: 3x3\n#3 @ 2,2: 1x1", 9);
+        assert_overlap_area("#1 @ 3,3: 2x2\n#2 @ 3,3: 2x2", 4);
+    }
+
+    #[test]
+    fn zero_size_claims() {
+        assert_overlap_area("#1 @ 0,0: 3x3\n#2 @ 1,0: 0x3\n#3 @ 0,1: 3x0", 0);
+        assert_overlap_area("#1 @ 0,0: 3x3\n#2 @ 1,1: 0x0\n#3 @ 0,0: 1x3", 3);
+        assert_overlap_area("#1 @ 4,4: 0x0", 0);
+        assert_overlap_area("", 0);
+    }
+
+    #[test]
+    fn claims_out_of_range() {
+        for claim in &[
+            "#1 @ 18446744073709551615,0: 1x1",
+            "#1 @ 0,18446744073709551615: 1x1",
+            "#1 @ 1,0: 18446744073709551615x1",
+        ] {
+            assert!(matches!(claim.parse::<Claim>(), Err(ClaimError::Extent(_))));
         }
+
+        let claim = "#1 @ 18446744073709551614,0: 1x1".parse::<Claim>().unwrap();
+        assert!(claim.covers(18446744073709551614, 0));
+    }
+
+    const SAMPLE: &str = "#1 @ 1,3: 4x4\n#2 @ 3,1: 4x4\n#3 @ 5,5: 2x2";
+
+    #[test]
+    fn overlap_graph() {
+        let graph = OverlapGraph::new(&parse_claims(SAMPLE).unwrap());
+        assert_eq!(graph.overlapping(0), [(1, 4)]);
+        assert_eq!(graph.overlapping(1), [(0, 4)]);
+        assert_eq!(graph.overlapping(2), []);
+        assert_eq!(graph.intact().collect::<Vec<usize>>(), [2]);
+        assert_eq!(graph.components(), [vec![0, 1], vec![2]]);
+
+        let chain = "#1 @ 0,0: 2x2\n#2 @ 1,1: 2x2\n#3 @ 2,2: 2x3\n#4 @ 10,10: 1x1\n#5 @ 3,0: 1x2";
+        let graph = OverlapGraph::new(&parse_claims(chain).unwrap());
+        assert_eq!(graph.overlapping(1), [(0, 1), (2, 1)]);
+        assert_eq!(graph.intact().collect::<Vec<usize>>(), [3, 4]);
+        assert_eq!(graph.components(), [vec![0, 1, 2], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn covering_edges() {
+        let claims = parse_claims(SAMPLE).unwrap();
+        let ids = |x, y| {
+            covering(&claims, x, y)
+                .map(|claim| claim.id)
+                .collect::<Vec<usize>>()
+        };
+
+        assert_eq!(ids(3, 3), [1, 2]);
+        assert_eq!(ids(4, 4), [1, 2]);
+        assert_eq!(ids(1, 3), [1]);
+        assert_eq!(ids(4, 6), [1]);
+        assert_eq!(ids(6, 6), [3]);
+        assert_eq!(ids(5, 5), [3]);
+        assert_eq!(ids(0, 3), []);
+        assert_eq!(ids(1, 7), []);
+        assert_eq!(ids(7, 1), []);
+    }
+
+    #[test]
+    fn claim_errors() {
+        for claim in &[
+            "",
+            "#1 @ 1,3 4x4",
+            "#1 @ 1,3: 4x",
+            "#a @ 1,3: 4x4",
+            "#1 @ -1,3: 4x4",
+        ] {
+            assert!(matches!(claim.parse::<Claim>(), Err(ClaimError::Format(_))));
+        }
+
+        match "#1 @ 1,99999999999999999999: 4x4".parse::<Claim>() {
+            Err(ClaimError::Field { name, value, .. }) => {
+                assert_eq!(name, "y");
+                assert_eq!(value, "99999999999999999999");
+            }
+            _ => panic!("expected a field error"),
+        }
+    }
+
+    #[test]
+    fn lenient_parsing() {
+        let input = "#1 @ 1,3: 4x4\nnot a claim\n#2 @ 3,1: 4x4\n#3 @ 1,99999999999999999999: 1x1";
+
+        let (claims, errors) = parse_claims_lenient(input);
+        assert_eq!(
+            claims.iter().map(|claim| claim.id).collect::<Vec<usize>>(),
+            [1, 2]
+        );
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.line)
+                .collect::<Vec<usize>>(),
+            [2, 4]
+        );
+        assert!(matches!(errors[0].error, ClaimError::Format(_)));
+        assert!(matches!(errors[1].error, ClaimError::Field { .. }));
+
+        let error = parse_claims(input).err().unwrap();
+        assert_eq!(error.line, 2);
+        assert_eq!(
+            error.to_string(),
+            "line 2: malformed claim \"not a claim\", expected \"#id @ x,y: lengthxheight\""
+        );
     }
 }
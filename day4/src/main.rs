@@ -1,113 +1,1396 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use std::collections::HashMap;
-use std::fs::read_to_string;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{read_to_string, remove_file, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Lines, Write};
+use std::path::PathBuf;
+use std::process;
 
 type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
 lazy_static! {
     static ref SHIFT_EVENT: Regex = Regex::new(
-        r"^\[\d\d\d\d\-\d\d\-\d\d \d\d:(?P<event_time>\d\d)\] (falls asleep|wakes up|Guard #(?P<id>\d+) begins shift)$"
+        r"^\[(?P<year>\d{4})-(?P<month>\d\d)-(?P<day>\d\d) (?P<hour>\d\d):(?P<minute>\d\d)\] (falls asleep|wakes up|Guard #(?P<id>\d+) begins shift)$"
     )
     .unwrap();
 }
 
-fn make_sleep_table(input: &str) -> Result<HashMap<usize, [usize; 60]>> {
-    let mut lines = input.lines().collect::<Vec<&str>>();
-    lines.sort_unstable();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    fn new(year: i64, month: u32, day: u32) -> Result<Self> {
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err(format!("Invalid date {:04}-{:02}-{:02}", year, month, day).into());
+        }
 
-    let mut sleep_table = HashMap::<usize, [usize; 60]>::new();
-    let mut current_guard = 0;
-    let mut sleep_time: Option<usize> = None;
-    for line in lines {
-        // Extract the event's time, and the guard's ID if it exists.
-        let parsed = SHIFT_EVENT.captures(line).ok_or("Erroneous line ?")?;
+        Ok(Date { year, month, day })
+    }
 
-        match parsed.name("id") {
-            Some(id) => {
-                // New shift. Assert that previous guard is not sleeping.
-                assert!(sleep_time.is_none());
+    // Days since 0000-03-01 in the proleptic Gregorian calendar. Starting the year in March
+    // puts the leap day last.
+    fn days(self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let month = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
 
-                current_guard = id.as_str().parse()?;
-                sleep_table
-                    .entry(current_guard)
-                    .or_insert_with(|| [0usize; 60]);
-            }
-            None => {
-                assert!(current_guard > 0);
-                match sleep_time {
-                    None => {
-                        // Fell asleep
-                        sleep_time = Some(parsed["event_time"].parse()?);
-                    }
-                    Some(start_time) => {
-                        // Woke-up
-                        let sleep_table = sleep_table
-                            .get_mut(&current_guard)
-                            .ok_or("Wake-up event for inexisting guard ?")?;
-                        let end_time = parsed["event_time"].parse()?;
-                        for i in start_time..end_time {
-                            // Mark the guard's array of sleep
-                            sleep_table[i] += 1;
-                        }
-                        sleep_time = None;
-                    }
+        365 * year + year.div_euclid(4) - year.div_euclid(100) + year.div_euclid(400) + day_of_year
+    }
+
+    fn next(self) -> Self {
+        if self.day < days_in_month(self.year, self.month) {
+            Date {
+                day: self.day + 1,
+                ..self
+            }
+        } else if self.month < 12 {
+            Date {
+                month: self.month + 1,
+                day: 1,
+                ..self
+            }
+        } else {
+            Date {
+                year: self.year + 1,
+                month: 1,
+                day: 1,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Timestamp {
+    date: Date,
+    hour: u32,
+    minute: u32,
+}
+
+impl Timestamp {
+    // Minutes since 0000-03-01 00:00.
+    fn minutes(self) -> i64 {
+        self.date.days() * 24 * 60 + i64::from(self.hour * 60 + self.minute)
+    }
+
+    // Shifts start around midnight. One starting in the evening is for the next day's midnight
+    // hour.
+    fn shift_date(self) -> Date {
+        if self.hour >= 12 {
+            self.date.next()
+        } else {
+            self.date
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:02}:{:02}", self.date, self.hour, self.minute)
+    }
+}
+
+// Variants in the order events of the same minute happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum EventKind {
+    BeginsShift(usize),
+    FallsAsleep,
+    WakesUp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Event {
+    time: Timestamp,
+    kind: EventKind,
+    // The log line the event was parsed from.
+    line: String,
+    // Index of the log file the line was read from.
+    file: usize,
+}
+
+impl Event {
+    fn new(line: &str) -> Result<Self> {
+        let parsed = SHIFT_EVENT
+            .captures(line)
+            .ok_or_else(|| format!("Erroneous line {} ?", line))?;
+
+        let (hour, minute) = (parsed["hour"].parse()?, parsed["minute"].parse()?);
+        if hour > 23 || minute > 59 {
+            return Err(format!("Invalid time in {}", line).into());
+        }
+        let time = Timestamp {
+            date: Date::new(
+                parsed["year"].parse()?,
+                parsed["month"].parse()?,
+                parsed["day"].parse()?,
+            )?,
+            hour,
+            minute,
+        };
+
+        let kind = match parsed.name("id") {
+            Some(id) => EventKind::BeginsShift(id.as_str().parse()?),
+            None if line.ends_with("falls asleep") => EventKind::FallsAsleep,
+            None => EventKind::WakesUp,
+        };
+
+        Ok(Event {
+            time,
+            kind,
+            line: line.to_string(),
+            file: 0,
+        })
+    }
+}
+
+// Inconsistencies found in a sorted log, with the offending lines.
+#[derive(Debug, PartialEq)]
+enum Anomaly {
+    // The guard falls asleep again without having woken up.
+    DoubleSleep {
+        asleep: String,
+        line: String,
+    },
+    // The guard wakes up without having fallen asleep.
+    WakeWithoutSleep {
+        line: String,
+    },
+    // A guard falls asleep or wakes up before the first shift begins.
+    EventBeforeShift {
+        line: String,
+    },
+    // The shift ends, with the next shift or the end of the log, while its guard is asleep.
+    AsleepAtShiftEnd {
+        asleep: String,
+        next_shift: Option<String>,
+    },
+    // Two events happen on the same minute.
+    DuplicateTimestamp {
+        first: String,
+        second: String,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::DoubleSleep { asleep, line } => {
+                write!(f, "falls asleep twice: \"{}\" then \"{}\"", asleep, line)
+            }
+            Anomaly::WakeWithoutSleep { line } => {
+                write!(f, "wakes up without falling asleep: \"{}\"", line)
+            }
+            Anomaly::EventBeforeShift { line } => {
+                write!(f, "event before any shift begins: \"{}\"", line)
+            }
+            Anomaly::AsleepAtShiftEnd {
+                asleep,
+                next_shift: Some(next_shift),
+            } => write!(
+                f,
+                "guard still asleep since \"{}\" when \"{}\"",
+                asleep, next_shift
+            ),
+            Anomaly::AsleepAtShiftEnd {
+                asleep,
+                next_shift: None,
+            } => write!(
+                f,
+                "guard still asleep since \"{}\" at the end of the log",
+                asleep
+            ),
+            Anomaly::DuplicateTimestamp { first, second } => {
+                write!(f, "duplicate timestamp: \"{}\" and \"{}\"", first, second)
+            }
+        }
+    }
+}
+
+struct LogError {
+    anomalies: Vec<Anomaly>,
+}
+
+// Returned from main, which prints errors with Debug.
+impl fmt::Debug for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} anomalies in the log:", self.anomalies.len())?;
+        for anomaly in &self.anomalies {
+            write!(f, "\n  {}", anomaly)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for LogError {}
+
+// Checks sorted events one at a time for every anomaly, instead of stopping at the first one.
+#[derive(Default)]
+struct Validator {
+    anomalies: Vec<Anomaly>,
+    previous: Option<Event>,
+    in_shift: bool,
+    asleep: Option<Event>,
+}
+
+impl Validator {
+    fn check(&mut self, event: &Event) {
+        if let Some(previous) = &self.previous {
+            if previous.time == event.time {
+                self.anomalies.push(Anomaly::DuplicateTimestamp {
+                    first: previous.line.clone(),
+                    second: event.line.clone(),
+                });
+            }
+        }
+        self.previous = Some(event.clone());
+
+        match event.kind {
+            EventKind::BeginsShift(_) => {
+                if let Some(asleep) = self.asleep.take() {
+                    self.anomalies.push(Anomaly::AsleepAtShiftEnd {
+                        asleep: asleep.line,
+                        next_shift: Some(event.line.clone()),
+                    });
+                }
+                self.in_shift = true;
+            }
+            _ if !self.in_shift => self.anomalies.push(Anomaly::EventBeforeShift {
+                line: event.line.clone(),
+            }),
+            EventKind::FallsAsleep => match &self.asleep {
+                Some(previous) => self.anomalies.push(Anomaly::DoubleSleep {
+                    asleep: previous.line.clone(),
+                    line: event.line.clone(),
+                }),
+                None => self.asleep = Some(event.clone()),
+            },
+            EventKind::WakesUp => {
+                if self.asleep.take().is_none() {
+                    self.anomalies.push(Anomaly::WakeWithoutSleep {
+                        line: event.line.clone(),
+                    });
                 }
             }
         }
     }
 
-    Ok(sleep_table)
+    fn finish(mut self) -> Vec<Anomaly> {
+        if let Some(asleep) = self.asleep.take() {
+            self.anomalies.push(Anomaly::AsleepAtShiftEnd {
+                asleep: asleep.line,
+                next_shift: None,
+            });
+        }
+
+        self.anomalies
+    }
+}
+
+// A guard's shift, for the midnight hour of its date.
+struct Shift {
+    date: Date,
+    guard: usize,
+    // Times asleep on each minute of the hour.
+    asleep: [usize; 60],
+    // Minutes asleep before or after the midnight hour, which no strategy looks at.
+    outside_hour: usize,
+    naps: usize,
 }
 
-fn part1(sleep_table: &HashMap<usize, [usize; 60]>) -> Result<()> {
-    // Find the guard that has the most minutes asleep
-    let (best_guard, minutes) = sleep_table
-        .iter()
-        .max_by_key(|(_, minutes)| minutes.iter().sum::<usize>())
-        .ok_or("No sleep guards ?")?;
+impl Shift {
+    // Count a nap's minutes that fall in the shift's midnight hour, and the others apart.
+    fn add_nap(&mut self, start: Timestamp, end: Timestamp) {
+        let hour_start = Timestamp {
+            date: self.date,
+            hour: 0,
+            minute: 0,
+        }
+        .minutes();
+        let (start, end) = (start.minutes(), end.minutes());
+        let (first, last) = (start.max(hour_start), end.min(hour_start + 60));
 
-    // What minute does that guard spend asleep the most?
-    let best_minute = minutes
+        for minute in first..last {
+            self.asleep[(minute - hour_start) as usize] += 1;
+        }
+        self.outside_hour += (end - start - (last - first).max(0)) as usize;
+        self.naps += 1;
+    }
+
+    // Row of the puzzle's timeline: date, guard, then '#' for minutes asleep and '.' otherwise.
+    fn timeline(&self) -> String {
+        let minutes = self
+            .asleep
+            .iter()
+            .map(|&times| if times > 0 { '#' } else { '.' })
+            .collect::<String>();
+
+        format!(
+            "{:02}-{:02}  {:<6}{}",
+            self.date.month,
+            self.date.day,
+            format!("#{}", self.guard),
+            minutes
+        )
+    }
+}
+
+fn print_timeline(shifts: &[Shift]) {
+    let tens = (0..60).map(|minute| (b'0' + minute / 10) as char);
+    let units = (0..60).map(|minute| (b'0' + minute % 10) as char);
+
+    println!("Date   ID    Minute");
+    println!("{:13}{}", "", tens.collect::<String>());
+    println!("{:13}{}", "", units.collect::<String>());
+    for shift in shifts {
+        println!("{}", shift.timeline());
+    }
+}
+
+struct GuardReport {
+    guard: usize,
+    total_minutes: usize,
+    outside_hour: usize,
+    naps: usize,
+    shifts: usize,
+    // Earliest of the minutes the guard is most often asleep, and how many times. None if the
+    // guard never slept during the midnight hour.
+    top_minute: Option<usize>,
+    top_minute_times: usize,
+}
+
+impl GuardReport {
+    fn average_nap(&self) -> f64 {
+        if self.naps == 0 {
+            0.0
+        } else {
+            (self.total_minutes + self.outside_hour) as f64 / self.naps as f64
+        }
+    }
+}
+
+// One report per guard, by increasing ID.
+fn guard_reports(shifts: &[Shift], sleep_table: &SleepTable) -> Vec<GuardReport> {
+    sleep_table
         .iter()
-        .enumerate()
-        .max_by_key(|(_, times)| *times)
-        .map(|(minute, _)| minute)
-        .ok_or("Can't find best minute ?")?;
+        .map(|(&guard, minutes)| {
+            let guard_shifts = shifts.iter().filter(|shift| shift.guard == guard);
+            let top_minute = top_minutes(minutes)[0];
+            let top_minute_times = minutes[top_minute];
 
-    assert_eq!(101262, best_guard * best_minute);
-    println!("Part1 {}", best_guard * best_minute);
+            GuardReport {
+                guard,
+                total_minutes: minutes.iter().sum(),
+                outside_hour: guard_shifts.clone().map(|shift| shift.outside_hour).sum(),
+                naps: guard_shifts.clone().map(|shift| shift.naps).sum(),
+                shifts: guard_shifts.count(),
+                top_minute: if top_minute_times > 0 {
+                    Some(top_minute)
+                } else {
+                    None
+                },
+                top_minute_times,
+            }
+        })
+        .collect()
+}
 
-    Ok(())
+fn print_reports(reports: &[GuardReport]) {
+    println!("Guard   Shifts  Asleep  Outside  Naps  Avg nap  Top minute");
+    for report in reports {
+        let top_minute = match report.top_minute {
+            Some(minute) => format!("{:02} ({}x)", minute, report.top_minute_times),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<8}{:>6}{:>8}{:>9}{:>6}{:>9.1}  {}",
+            format!("#{}", report.guard),
+            report.shifts,
+            report.total_minutes,
+            report.outside_hour,
+            report.naps,
+            report.average_nap(),
+            top_minute
+        );
+    }
+}
+
+// Read the events of all the logs, sorted.
+fn read_events(files: &[&str]) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for (idx, file) in files.iter().enumerate() {
+        for line in read_to_string(file)?.lines() {
+            events.push(Event {
+                file: idx,
+                ..Event::new(line)?
+            });
+        }
+    }
+    events.sort_unstable();
+
+    Ok(events)
+}
+
+// Sorted events with those logged in several files kept once. Duplicates within a single file
+// are kept, for the validator to report: an event is kept as many times as the file that logs it
+// the most times has it.
+struct Deduplicated<I> {
+    events: I,
+    previous: Option<(Timestamp, EventKind)>,
+    // Copies of the previous event seen in each file, and how many were kept.
+    copies: HashMap<usize, usize>,
+    kept: usize,
+}
+
+impl<I> Deduplicated<I> {
+    fn new(events: I) -> Self {
+        Deduplicated {
+            events,
+            previous: None,
+            copies: HashMap::new(),
+            kept: 0,
+        }
+    }
+}
+
+impl<I> Iterator for Deduplicated<I>
+where
+    I: Iterator<Item = Result<Event>>,
+{
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for event in &mut self.events {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => return Some(Err(error)),
+            };
+
+            let key = (event.time, event.kind);
+            if self.previous != Some(key) {
+                self.previous = Some(key);
+                self.copies.clear();
+                self.kept = 0;
+            }
+
+            let copies = self.copies.entry(event.file).or_default();
+            *copies += 1;
+            if *copies > self.kept {
+                self.kept += 1;
+                return Some(Ok(event));
+            }
+        }
+
+        None
+    }
+}
+
+// Most runs merged at once, each one an open file.
+const MAX_MERGED_RUNS: usize = 64;
+
+// Events of logs too large to be sorted in memory: they're split into sorted runs of at most
+// run_size events written to temporary files, which are then merged.
+struct ExternalSort {
+    runs: Vec<PathBuf>,
+    // Runs created so far, to name them.
+    created: usize,
+}
+
+impl ExternalSort {
+    fn new(files: &[&str], run_size: usize) -> Result<Self> {
+        let mut sort = ExternalSort {
+            runs: Vec::new(),
+            created: 0,
+        };
+        let mut events = Vec::with_capacity(run_size);
+        for (idx, file) in files.iter().enumerate() {
+            for line in BufReader::new(File::open(file)?).lines() {
+                events.push(Event {
+                    file: idx,
+                    ..Event::new(&line?)?
+                });
+                if events.len() >= run_size {
+                    sort.write_run(&mut events)?;
+                }
+            }
+        }
+        if !events.is_empty() {
+            sort.write_run(&mut events)?;
+        }
+
+        Ok(sort)
+    }
+
+    // A new file in the shared temporary directory. It's never one already there, which might
+    // be someone else's file or a link planted at a predictable name.
+    fn create_run(&mut self) -> Result<File> {
+        for attempt in 0..100 {
+            let path = env::temp_dir().join(format!(
+                "day4-run-{}-{}-{}.txt",
+                process::id(),
+                self.created,
+                attempt
+            ));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    // Remove the file along with the others, even if writing it fails.
+                    self.runs.push(path);
+                    self.created += 1;
+                    return Ok(file);
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Err("No free name for a temporary run file".into())
+    }
+
+    fn write_run(&mut self, events: &mut Vec<Event>) -> Result<()> {
+        events.sort_unstable();
+        self.write_events(events.drain(..).map(Ok))
+    }
+
+    // Write already sorted events to a new run.
+    fn write_events<I>(&mut self, events: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<Event>>,
+    {
+        let mut writer = BufWriter::new(self.create_run()?);
+        for event in events {
+            let event = event?;
+            writeln!(writer, "{} {}", event.file, event.line)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    // With more than MAX_MERGED_RUNS runs, merge the first ones into a new run, as many times
+    // as needed, before merging the last ones.
+    fn merge(&mut self) -> Result<MergedEvents> {
+        while self.runs.len() > MAX_MERGED_RUNS {
+            let runs = self.runs.drain(..MAX_MERGED_RUNS).collect::<Vec<PathBuf>>();
+            let written = MergedEvents::new(&runs).and_then(|merged| self.write_events(merged));
+            for path in &runs {
+                // Nothing more to do if the file can't be removed.
+                let _ = remove_file(path);
+            }
+            written?;
+        }
+
+        MergedEvents::new(&self.runs)
+    }
+}
+
+impl Drop for ExternalSort {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            // Nothing more to do if the file can't be removed.
+            let _ = remove_file(path);
+        }
+    }
+}
+
+// K-way merge of the sorted runs.
+struct MergedEvents {
+    // Lines of a run are the file index of the event, a space, then its log line.
+    runs: Vec<Lines<BufReader<File>>>,
+    // Smallest unmerged event of each run, along with the run's index.
+    heads: BinaryHeap<Reverse<(Event, usize)>>,
+}
+
+impl MergedEvents {
+    fn new(runs: &[PathBuf]) -> Result<Self> {
+        let mut merged = MergedEvents {
+            runs: Vec::new(),
+            heads: BinaryHeap::new(),
+        };
+        for path in runs {
+            merged.runs.push(BufReader::new(File::open(path)?).lines());
+            merged.advance(merged.runs.len() - 1)?;
+        }
+
+        Ok(merged)
+    }
+
+    fn advance(&mut self, run: usize) -> Result<()> {
+        if let Some(line) = self.runs[run].next() {
+            let line = line?;
+            let (file, line) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("Corrupted run line {}", line))?;
+            let event = Event {
+                file: file.parse()?,
+                ..Event::new(line)?
+            };
+            self.heads.push(Reverse((event, run)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for MergedEvents {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((event, run)) = self.heads.pop()?;
+        if let Err(error) = self.advance(run) {
+            return Some(Err(error));
+        }
+
+        Some(Ok(event))
+    }
+}
+
+// Using a BTreeMap to iterate guards by increasing ID, which breaks ties deterministically.
+type SleepTable = BTreeMap<usize, [usize; 60]>;
+
+// What's kept of a log: the sleep table, and the shifts only when asked for, as they take more
+// memory than the log itself.
+struct Log {
+    sleep_table: SleepTable,
+    shifts: Option<Vec<Shift>>,
+}
+
+impl Log {
+    fn add_shift(&mut self, shift: Shift) {
+        let minutes = self
+            .sleep_table
+            .entry(shift.guard)
+            .or_insert_with(|| [0usize; 60]);
+        for (total, asleep) in minutes.iter_mut().zip(shift.asleep.iter()) {
+            *total += asleep;
+        }
+
+        if let Some(shifts) = &mut self.shifts {
+            shifts.push(shift);
+        }
+    }
+}
+
+// Group sorted events into shifts, added to the sleep table as each one ends, so only the current
+// shift is held in memory unless keep_shifts. The log is validated on the way, and rejected with
+// every anomaly found.
+fn read_log<I>(events: I, keep_shifts: bool) -> Result<Log>
+where
+    I: IntoIterator<Item = Result<Event>>,
+{
+    let mut log = Log {
+        sleep_table: SleepTable::new(),
+        shifts: if keep_shifts { Some(Vec::new()) } else { None },
+    };
+    let mut validator = Validator::default();
+    let mut shift: Option<Shift> = None;
+    let mut sleep_time: Option<Timestamp> = None;
+    for event in events {
+        let event = event?;
+        validator.check(&event);
+
+        match event.kind {
+            EventKind::BeginsShift(guard) => {
+                sleep_time = None;
+                let next = Shift {
+                    date: event.time.shift_date(),
+                    guard,
+                    asleep: [0; 60],
+                    outside_hour: 0,
+                    naps: 0,
+                };
+                if let Some(previous) = shift.replace(next) {
+                    log.add_shift(previous);
+                }
+            }
+            EventKind::FallsAsleep => {
+                sleep_time = sleep_time.or(Some(event.time));
+            }
+            EventKind::WakesUp => {
+                if let (Some(shift), Some(start_time)) = (shift.as_mut(), sleep_time.take()) {
+                    shift.add_nap(start_time, event.time);
+                }
+            }
+        }
+    }
+    if let Some(last) = shift {
+        log.add_shift(last);
+    }
+
+    let anomalies = validator.finish();
+    if !anomalies.is_empty() {
+        return Err(LogError { anomalies }.into());
+    }
+
+    Ok(log)
+}
+
+// A strategy's pick. On ties, the lowest guard ID and then the earliest minute win; all the
+// candidates that tied are kept to be reported.
+struct Choice {
+    guard: usize,
+    minute: usize,
+    tied_guards: Vec<usize>,
+    tied_minutes: Vec<usize>,
+}
+
+impl Choice {
+    fn new(sleep_table: &SleepTable, tied_guards: Vec<usize>) -> Result<Self> {
+        let guard = *tied_guards.first().ok_or("No sleep guards ?")?;
+        let tied_minutes = top_minutes(&sleep_table[&guard]);
+
+        Ok(Choice {
+            guard,
+            minute: tied_minutes[0],
+            tied_guards,
+            tied_minutes,
+        })
+    }
+
+    fn print_ties(&self, strategy: &str) {
+        if self.tied_guards.len() > 1 {
+            println!(
+                "{}: guards {:?} tied, picked #{}",
+                strategy, self.tied_guards, self.guard
+            );
+        }
+        if self.tied_minutes.len() > 1 {
+            println!(
+                "{}: minutes {:?} tied for guard #{}, picked {}",
+                strategy, self.tied_minutes, self.guard, self.minute
+            );
+        }
+    }
+}
+
+// Minutes a guard spent asleep the most times, earliest first.
+fn top_minutes(minutes: &[usize; 60]) -> Vec<usize> {
+    let most = minutes.iter().max().copied().unwrap_or(0);
+
+    (0..60).filter(|&minute| minutes[minute] == most).collect()
 }
 
-fn part2(sleep_table: &HashMap<usize, [usize; 60]>) -> Result<()> {
-    // Of all guards, which guard is most frequently asleep on the same minute?
-    let (best_guard, minutes) = sleep_table
+// Guards with the highest score, by increasing ID.
+fn top_guards<F>(sleep_table: &SleepTable, score: F) -> Vec<usize>
+where
+    F: Fn(&[usize; 60]) -> usize,
+{
+    let best = sleep_table.values().map(&score).max().unwrap_or(0);
+
+    sleep_table
         .iter()
-        .max_by_key(|(_, minutes)| minutes.iter().max())
-        .ok_or("Can't find guard with most frequent sleeps ?")?;
+        .filter(|(_, minutes)| score(minutes) == best)
+        .map(|(&guard, _)| guard)
+        .collect()
+}
+
+// Candidates (guard, minute) with the highest score, ordered by guard and then minute.
+fn top_candidates<I>(candidates: I) -> Result<Choice>
+where
+    I: IntoIterator<Item = ((usize, usize), usize)>,
+{
+    let candidates = candidates.into_iter().collect::<Vec<_>>();
+    let best = candidates.iter().map(|&(_, score)| score).max();
+    let mut tied = candidates
+        .into_iter()
+        .filter(|&(_, score)| Some(score) == best)
+        .map(|(candidate, _)| candidate)
+        .collect::<Vec<(usize, usize)>>();
+    tied.sort_unstable();
 
-    let top_minute = minutes
+    let &(guard, minute) = tied.first().ok_or("No sleep guards ?")?;
+    let mut tied_guards = tied.iter().map(|&(guard, _)| guard).collect::<Vec<usize>>();
+    tied_guards.dedup();
+    let tied_minutes = tied
         .iter()
-        .enumerate()
-        .max_by_key(|(_, &value)| value)
-        .map(|(idx, _)| idx)
-        .ok_or("Can't find the top minute ?")?;
+        .filter(|&&(tied_guard, _)| tied_guard == guard)
+        .map(|&(_, minute)| minute)
+        .collect();
 
-    assert_eq!(71976, best_guard * top_minute);
-    println!("Part2 {}", best_guard * top_minute);
+    Ok(Choice {
+        guard,
+        minute,
+        tied_guards,
+        tied_minutes,
+    })
+}
 
-    Ok(())
+// A query over the sleep table, picking a guard and a minute.
+trait Strategy {
+    fn name(&self) -> String;
+    fn choose(&self, sleep_table: &SleepTable) -> Result<Choice>;
+}
+
+// Strategy 1: the guard with the most minutes asleep, and the minute that guard spends asleep
+// the most.
+struct MostAsleep;
+
+impl Strategy for MostAsleep {
+    fn name(&self) -> String {
+        "most-asleep".to_string()
+    }
+
+    fn choose(&self, sleep_table: &SleepTable) -> Result<Choice> {
+        Choice::new(
+            sleep_table,
+            top_guards(sleep_table, |minutes| minutes.iter().sum()),
+        )
+    }
+}
+
+// Strategy 2: the guard most frequently asleep on the same minute.
+struct MostFrequentMinute;
+
+impl Strategy for MostFrequentMinute {
+    fn name(&self) -> String {
+        "most-frequent-minute".to_string()
+    }
+
+    fn choose(&self, sleep_table: &SleepTable) -> Result<Choice> {
+        top_candidates(sleep_table.iter().flat_map(|(&guard, minutes)| {
+            (0..60).map(move |minute| ((guard, minute), minutes[minute]))
+        }))
+    }
+}
+
+// The guard whose sleep is the least evenly spread over the hour, and their top minute.
+struct HighestVariance;
+
+impl Strategy for HighestVariance {
+    fn name(&self) -> String {
+        "highest-variance".to_string()
+    }
+
+    fn choose(&self, sleep_table: &SleepTable) -> Result<Choice> {
+        // Variance of the minutes times 60², which keeps it an integer:
+        // 60 · Σx² - (Σx)²
+        let scaled_variance = |minutes: &[usize; 60]| {
+            let sum = minutes.iter().sum::<usize>();
+            let squares = minutes.iter().map(|times| times * times).sum::<usize>();
+            60 * squares - sum * sum
+        };
+
+        Choice::new(sleep_table, top_guards(sleep_table, scaled_variance))
+    }
+}
+
+// The minute on which the most guards have been asleep, and the guard asleep on it the most.
+struct BusiestMinute;
+
+impl Strategy for BusiestMinute {
+    fn name(&self) -> String {
+        "busiest-minute".to_string()
+    }
+
+    fn choose(&self, sleep_table: &SleepTable) -> Result<Choice> {
+        let sleepers = (0..60)
+            .map(|minute| {
+                sleep_table
+                    .values()
+                    .filter(|minutes| minutes[minute] > 0)
+                    .count()
+            })
+            .collect::<Vec<usize>>();
+        let most = sleepers.iter().max().copied().unwrap_or(0);
+        let busiest = (0..60)
+            .filter(|&minute| sleepers[minute] == most)
+            .collect::<Vec<usize>>();
+
+        top_candidates(sleep_table.iter().flat_map(|(&guard, minutes)| {
+            busiest
+                .iter()
+                .map(move |&minute| ((guard, minute), minutes[minute]))
+        }))
+    }
+}
+
+// The guard and window of consecutive minutes, by its first minute, that add up to the most
+// times asleep: the best time to sneak in.
+struct BestWindow {
+    length: usize,
+}
+
+impl Strategy for BestWindow {
+    fn name(&self) -> String {
+        format!("window:{}", self.length)
+    }
+
+    fn choose(&self, sleep_table: &SleepTable) -> Result<Choice> {
+        if self.length == 0 || self.length > 60 {
+            return Err(format!("Window of {} minutes out of the hour", self.length).into());
+        }
+
+        top_candidates(sleep_table.iter().flat_map(|(&guard, minutes)| {
+            minutes
+                .windows(self.length)
+                .enumerate()
+                .map(move |(start, window)| ((guard, start), window.iter().sum()))
+        }))
+    }
+}
+
+fn strategy(name: &str) -> Result<Box<dyn Strategy>> {
+    match name {
+        "most-asleep" => Ok(Box::new(MostAsleep)),
+        "most-frequent-minute" => Ok(Box::new(MostFrequentMinute)),
+        "highest-variance" => Ok(Box::new(HighestVariance)),
+        "busiest-minute" => Ok(Box::new(BusiestMinute)),
+        _ => match name.strip_prefix("window:") {
+            Some(length) => Ok(Box::new(BestWindow {
+                length: length.parse()?,
+            })),
+            None => Err(format!("Unknown strategy {}", name).into()),
+        },
+    }
+}
+
+fn part1(sleep_table: &SleepTable, files: &[&str]) -> Result<Choice> {
+    let choice = MostAsleep.choose(sleep_table)?;
+
+    if files == ["input.txt"] {
+        assert_eq!(101262, choice.guard * choice.minute);
+    }
+    println!("Part1 {}", choice.guard * choice.minute);
+
+    Ok(choice)
+}
+
+fn part2(sleep_table: &SleepTable, files: &[&str]) -> Result<Choice> {
+    let choice = MostFrequentMinute.choose(sleep_table)?;
+
+    if files == ["input.txt"] {
+        assert_eq!(71976, choice.guard * choice.minute);
+    }
+    println!("Part2 {}", choice.guard * choice.minute);
+
+    Ok(choice)
 }
 
 fn main() -> Result<()> {
-    let input = read_to_string("input.txt")?;
+    // --timeline: draw each shift's minutes asleep, like the puzzle's table.
+    // --report: print sleep statistics for each guard.
+    // --ties: report the candidates that tied in each strategy.
+    // --log <file>: a log to read, can be repeated. input.txt by default.
+    // --external-sort <events>: sort the logs through temporary files, with at most that many
+    // events in memory.
+    // --strategy <name>: also run a strategy among most-asleep, most-frequent-minute,
+    // highest-variance, busiest-minute and window:<minutes>. Can be repeated.
+    let args = env::args().collect::<Vec<String>>();
+
+    let mut files = args
+        .windows(2)
+        .filter(|pair| pair[0] == "--log")
+        .map(|pair| pair[1].as_str())
+        .collect::<Vec<&str>>();
+    if files.is_empty() {
+        files.push("input.txt");
+    }
+
+    let timeline = args.iter().any(|arg| arg == "--timeline");
+    let report = args.iter().any(|arg| arg == "--report");
+    let keep_shifts = timeline || report;
 
-    let sleep_table = make_sleep_table(&input)?;
-    part1(&sleep_table)?;
-    part2(&sleep_table)?;
+    let Log {
+        sleep_table,
+        shifts,
+    } = match option_value(&args, "--external-sort")? {
+        Some(run_size) => {
+            let run_size = run_size.parse::<usize>()?.max(1);
+            let mut sort = ExternalSort::new(&files, run_size)?;
+            read_log(Deduplicated::new(sort.merge()?), keep_shifts)?
+        }
+        None => read_log(
+            Deduplicated::new(read_events(&files)?.into_iter().map(Ok)),
+            keep_shifts,
+        )?,
+    };
+    let shifts = shifts.unwrap_or_default();
+    let strategy1 = part1(&sleep_table, &files)?;
+    let strategy2 = part2(&sleep_table, &files)?;
+
+    let ties = args.iter().any(|arg| arg == "--ties");
+    if ties {
+        strategy1.print_ties("Strategy 1");
+        strategy2.print_ties("Strategy 2");
+    }
+    for name in args
+        .windows(2)
+        .filter(|pair| pair[0] == "--strategy")
+        .map(|pair| pair[1].as_str())
+    {
+        let strategy = strategy(name)?;
+        let choice = strategy.choose(&sleep_table)?;
+        println!(
+            "{}: guard #{}, minute {}: {}",
+            strategy.name(),
+            choice.guard,
+            choice.minute,
+            choice.guard * choice.minute
+        );
+        if ties {
+            choice.print_ties(&strategy.name());
+        }
+    }
+    if timeline {
+        print_timeline(&shifts);
+    }
+    if report {
+        print_reports(&guard_reports(&shifts, &sleep_table));
+    }
 
     Ok(())
 }
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => Ok(Some(
+            args.get(idx + 1)
+                .ok_or(format!("{} expects a value", name))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shifts(log: &str) -> Vec<Shift> {
+        read_log(log.lines().map(Event::new), true)
+            .unwrap()
+            .shifts
+            .unwrap()
+    }
+
+    #[test]
+    fn nap_across_the_midnight_hour() {
+        let shifts = shifts(
+            "[1518-11-01 23:50] Guard #10 begins shift\n\
+             [1518-11-01 23:59] falls asleep\n\
+             [1518-11-02 01:05] wakes up",
+        );
+
+        assert_eq!(shifts.len(), 1);
+        assert_eq!(shifts[0].date, Date::new(1518, 11, 2).unwrap());
+        assert!(shifts[0].asleep.iter().all(|&times| times == 1));
+        assert_eq!(shifts[0].outside_hour, 6);
+        assert_eq!(shifts[0].naps, 1);
+    }
+
+    #[test]
+    fn nap_outside_the_midnight_hour() {
+        let shifts = shifts(
+            "[1518-11-01 23:50] Guard #10 begins shift\n\
+             [1518-11-01 23:52] falls asleep\n\
+             [1518-11-01 23:55] wakes up\n\
+             [1518-11-02 00:10] falls asleep\n\
+             [1518-11-02 00:12] wakes up",
+        );
+
+        assert_eq!(shifts[0].asleep.iter().sum::<usize>(), 2);
+        assert_eq!(shifts[0].asleep[10..12], [1, 1]);
+        assert_eq!(shifts[0].outside_hour, 3);
+        assert_eq!(shifts[0].naps, 2);
+    }
+
+    // Sleep table of guards, each with the times asleep on some minutes.
+    fn sleep_table(guards: &[(usize, &[(usize, usize)])]) -> SleepTable {
+        guards
+            .iter()
+            .map(|&(guard, times)| {
+                let mut minutes = [0; 60];
+                for &(minute, count) in times {
+                    minutes[minute] = count;
+                }
+                (guard, minutes)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn most_asleep_ties() {
+        let sleep_table = sleep_table(&[
+            (20, &[(30, 2), (5, 2)]),
+            (3, &[(1, 3)]),
+            (7, &[(40, 2), (12, 2)]),
+        ]);
+        let choice = MostAsleep.choose(&sleep_table).unwrap();
+
+        assert_eq!((choice.guard, choice.minute), (7, 12));
+        assert_eq!(choice.tied_guards, [7, 20]);
+        assert_eq!(choice.tied_minutes, [12, 40]);
+    }
+
+    #[test]
+    fn most_frequent_minute_ties() {
+        let sleep_table = sleep_table(&[
+            (20, &[(30, 2), (5, 2)]),
+            (3, &[(1, 1)]),
+            (7, &[(40, 2), (12, 2), (13, 1)]),
+        ]);
+        let choice = MostFrequentMinute.choose(&sleep_table).unwrap();
+
+        assert_eq!((choice.guard, choice.minute), (7, 12));
+        assert_eq!(choice.tied_guards, [7, 20]);
+        assert_eq!(choice.tied_minutes, [12, 40]);
+    }
+
+    #[test]
+    fn no_ties() {
+        let sleep_table = sleep_table(&[(20, &[(30, 3)]), (7, &[(40, 2), (12, 2)])]);
+        let choice = MostFrequentMinute.choose(&sleep_table).unwrap();
+
+        assert_eq!((choice.guard, choice.minute), (20, 30));
+        assert_eq!(choice.tied_guards, [20]);
+        assert_eq!(choice.tied_minutes, [30]);
+    }
+
+    #[test]
+    fn highest_variance() {
+        let sleep_table = sleep_table(&[
+            (20, &[(0, 1), (1, 1), (2, 1), (3, 1), (4, 1), (5, 1)]),
+            (7, &[(10, 6)]),
+            (3, &[(45, 6)]),
+        ]);
+        let choice = HighestVariance.choose(&sleep_table).unwrap();
+
+        assert_eq!((choice.guard, choice.minute), (3, 45));
+        assert_eq!(choice.tied_guards, [3, 7]);
+        assert_eq!(choice.tied_minutes, [45]);
+    }
+
+    #[test]
+    fn busiest_minute() {
+        let sleep_table = sleep_table(&[
+            (20, &[(10, 5)]),
+            (3, &[(20, 1), (30, 1)]),
+            (7, &[(20, 2), (30, 2)]),
+        ]);
+        let choice = BusiestMinute.choose(&sleep_table).unwrap();
+
+        // Minutes 20 and 30 have two guards asleep each, not the one guard asleep the most.
+        assert_eq!((choice.guard, choice.minute), (7, 20));
+        assert_eq!(choice.tied_guards, [7]);
+        assert_eq!(choice.tied_minutes, [20, 30]);
+    }
+
+    #[test]
+    fn best_window() {
+        let sleep_table =
+            sleep_table(&[(20, &[(30, 2), (31, 2), (32, 2)]), (7, &[(10, 3), (11, 3)])]);
+        let choose = |length| BestWindow { length }.choose(&sleep_table).unwrap();
+
+        let choice = choose(2);
+        assert_eq!((choice.guard, choice.minute), (7, 10));
+        assert_eq!(choice.tied_guards, [7]);
+        assert_eq!(choice.tied_minutes, [10]);
+
+        let choice = choose(3);
+        assert_eq!((choice.guard, choice.minute), (7, 9));
+        assert_eq!(choice.tied_guards, [7, 20]);
+        assert_eq!(choice.tied_minutes, [9, 10]);
+
+        let choice = choose(60);
+        assert_eq!((choice.guard, choice.minute), (7, 0));
+        assert_eq!(choice.tied_guards, [7, 20]);
+        assert_eq!(choice.tied_minutes, [0]);
+    }
+
+    #[test]
+    fn strategy_names() {
+        for &name in &[
+            "most-asleep",
+            "most-frequent-minute",
+            "highest-variance",
+            "busiest-minute",
+            "window:1",
+            "window:60",
+        ] {
+            assert_eq!(strategy(name).unwrap().name(), name);
+        }
+
+        assert!(strategy("least-asleep").is_err());
+        assert!(strategy("window:").is_err());
+        assert!(strategy("window:-1").is_err());
+
+        let sleep_table = sleep_table(&[(7, &[(10, 1)])]);
+        for &name in &["window:0", "window:61"] {
+            assert!(strategy(name).unwrap().choose(&sleep_table).is_err());
+        }
+    }
+
+    #[test]
+    fn duplicates_across_files_only() {
+        let events = [
+            (0, "[1518-11-01 00:00] Guard #10 begins shift"),
+            (1, "[1518-11-01 00:00] Guard #10 begins shift"),
+            (0, "[1518-11-01 00:05] falls asleep"),
+            (0, "[1518-11-01 00:05] falls asleep"),
+            (1, "[1518-11-01 00:05] falls asleep"),
+            (1, "[1518-11-01 00:25] wakes up"),
+        ]
+        .iter()
+        .map(|&(file, line)| {
+            Ok(Event {
+                file,
+                ..Event::new(line)?
+            })
+        })
+        .collect::<Vec<Result<Event>>>();
+
+        let lines = Deduplicated::new(events.into_iter())
+            .map(|event| event.unwrap().line)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            lines,
+            [
+                "[1518-11-01 00:00] Guard #10 begins shift",
+                "[1518-11-01 00:05] falls asleep",
+                "[1518-11-01 00:05] falls asleep",
+                "[1518-11-01 00:25] wakes up",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_anomaly() {
+        let log = [
+            "[1518-11-01 00:00] falls asleep",
+            "[1518-11-01 00:01] Guard #10 begins shift",
+            "[1518-11-01 00:05] falls asleep",
+            "[1518-11-01 00:06] falls asleep",
+            "[1518-11-01 00:10] wakes up",
+            "[1518-11-01 00:11] wakes up",
+            "[1518-11-01 00:20] falls asleep",
+            "[1518-11-01 23:58] Guard #11 begins shift",
+            "[1518-11-02 00:30] falls asleep",
+            "[1518-11-02 00:30] wakes up",
+            "[1518-11-02 00:40] falls asleep",
+        ];
+        let error = read_log(log.iter().map(|line| Event::new(line)), false)
+            .err()
+            .unwrap()
+            .downcast::<LogError>()
+            .unwrap();
+
+        let line = |idx: usize| log[idx].to_string();
+        assert_eq!(
+            error.anomalies,
+            [
+                Anomaly::EventBeforeShift { line: line(0) },
+                Anomaly::DoubleSleep {
+                    asleep: line(2),
+                    line: line(3),
+                },
+                Anomaly::WakeWithoutSleep { line: line(5) },
+                Anomaly::AsleepAtShiftEnd {
+                    asleep: line(6),
+                    next_shift: Some(line(7)),
+                },
+                Anomaly::DuplicateTimestamp {
+                    first: line(8),
+                    second: line(9),
+                },
+                Anomaly::AsleepAtShiftEnd {
+                    asleep: line(10),
+                    next_shift: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_without_sleep() {
+        let log = read_log(
+            "[1518-11-01 00:00] Guard #10 begins shift\n\
+             [1518-11-01 23:58] Guard #11 begins shift\n\
+             [1518-11-02 00:00] falls asleep\n\
+             [1518-11-02 00:02] wakes up"
+                .lines()
+                .map(Event::new),
+            true,
+        )
+        .unwrap();
+        let reports = guard_reports(&log.shifts.unwrap(), &log.sleep_table);
+
+        assert_eq!(reports[0].guard, 10);
+        assert_eq!(reports[0].top_minute, None);
+        assert_eq!(reports[1].guard, 11);
+        assert_eq!(reports[1].top_minute, Some(0));
+        assert_eq!(reports[1].top_minute_times, 1);
+    }
+
+    #[test]
+    fn external_sort_merges_in_passes() {
+        let paths = (0..2)
+            .map(|idx| {
+                env::temp_dir()
+                    .join(format!("day4-test-{}-{}.txt", process::id(), idx))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<String>>();
+        for (idx, path) in paths.iter().enumerate() {
+            // Unsorted, with half the events logged in both files.
+            let log = (0..200)
+                .rev()
+                .map(|event| event + idx * 100)
+                .map(|event| {
+                    format!(
+                        "[1518-{:02}-{:02} 00:{:02}] falls asleep\n",
+                        event % 12 + 1,
+                        event % 28 + 1,
+                        event % 60
+                    )
+                })
+                .collect::<String>();
+            File::create(path)
+                .unwrap()
+                .write_all(log.as_bytes())
+                .unwrap();
+        }
+        let files = paths.iter().map(String::as_str).collect::<Vec<&str>>();
+
+        let mut sort = ExternalSort::new(&files, 1).unwrap();
+        assert!(sort.runs.len() > MAX_MERGED_RUNS);
+        let merged = sort.merge().unwrap().collect::<Result<Vec<Event>>>();
+        let expected = read_events(&files);
+        for path in &paths {
+            remove_file(path).unwrap();
+        }
+
+        assert_eq!(merged.unwrap(), expected.unwrap());
+    }
+}
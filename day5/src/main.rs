@@ -1,55 +1,827 @@
-use std::fs::read_to_string;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{read_to_string, remove_file, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
 
 type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
-fn react_polymer(input: &str) -> String {
-    let mut polymer = String::with_capacity(input.len());
+// Trim surrounding whitespace, such as the trailing newline, and make sure every unit is an
+// ASCII letter.
+fn parse_polymer(input: &str) -> Result<&str> {
+    let polymer = input.trim();
 
-    for current in input.bytes() {
-        if let Some(previous) = polymer.bytes().last() {
-            if previous + 32 == current || previous - 32 == current {
-                polymer.pop();
+    if let Some((position, unit)) = polymer
+        .char_indices()
+        .find(|(_, unit)| !unit.is_ascii_alphabetic())
+    {
+        return Err(format!(
+            "Invalid unit {:?} at position {}, polymers are made of ASCII letters",
+            unit, position
+        )
+        .into());
+    }
+
+    Ok(polymer)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reaction {
+    // Both units are destroyed.
+    Annihilate,
+    // Both units are replaced by a single one, which may react in turn.
+    Transform(u8),
+}
+
+// What happens when a unit is next to the previous one, if anything.
+trait Rules {
+    fn react(&self, previous: u8, current: u8) -> Option<Reaction>;
+}
+
+impl<F> Rules for F
+where
+    F: Fn(u8, u8) -> Option<Reaction>,
+{
+    fn react(&self, previous: u8, current: u8) -> Option<Reaction> {
+        self(previous, current)
+    }
+}
+
+// The puzzle's rule: units annihilate when they're the same letter, in opposite case.
+struct OppositeCase;
+
+impl Rules for OppositeCase {
+    fn react(&self, previous: u8, current: u8) -> Option<Reaction> {
+        if previous != current && previous.eq_ignore_ascii_case(&current) {
+            Some(Reaction::Annihilate)
+        } else {
+            None
+        }
+    }
+}
+
+// Rules read from a file, one per line: "ab" for a pair of units that annihilate, "ab c" for a
+// pair that turns into c. Pairs are ordered, "ab" doesn't make "ba" react.
+struct RuleTable {
+    rules: HashMap<(u8, u8), Reaction>,
+}
+
+impl RuleTable {
+    fn new(input: &str) -> Result<Self> {
+        let mut rules = HashMap::new();
+        for line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let reaction = match fields.as_slice() {
+                [pair] if pair.len() == 2 && pair.is_ascii() => Reaction::Annihilate,
+                [pair, product] if pair.len() == 2 && product.len() == 1 && line.is_ascii() => {
+                    Reaction::Transform(product.as_bytes()[0])
+                }
+                _ => {
+                    return Err(
+                        format!("Invalid rule {:?}, expected \"ab\" or \"ab c\"", line).into(),
+                    )
+                }
+            };
+
+            let pair = fields[0].as_bytes();
+            rules.insert((pair[0], pair[1]), reaction);
+        }
+
+        Ok(RuleTable { rules })
+    }
+}
+
+impl Rules for RuleTable {
+    fn react(&self, previous: u8, current: u8) -> Option<Reaction> {
+        self.rules.get(&(previous, current)).copied()
+    }
+}
+
+// What the reducer stacks: a unit alone, or along with its position in the original polymer.
+trait Unit: Copy {
+    fn unit(self) -> u8;
+    // This unit turned into the product of its reaction.
+    fn transform(self, product: u8) -> Self;
+}
+
+impl Unit for u8 {
+    fn unit(self) -> u8 {
+        self
+    }
+
+    fn transform(self, product: u8) -> Self {
+        product
+    }
+}
+
+// A product takes the position of the right unit of the pair it comes from.
+impl Unit for (usize, u8) {
+    fn unit(self) -> u8 {
+        self.1
+    }
+
+    fn transform(self, product: u8) -> Self {
+        (self.0, product)
+    }
+}
+
+// Where the reducer keeps the units left.
+trait Stack<T> {
+    fn last(&mut self) -> io::Result<Option<T>>;
+    fn pop(&mut self) -> io::Result<Option<T>>;
+    fn push(&mut self, unit: T) -> io::Result<()>;
+}
+
+// In memory, never fails.
+impl<T: Copy> Stack<T> for Vec<T> {
+    fn last(&mut self) -> io::Result<Option<T>> {
+        Ok(self.as_slice().last().copied())
+    }
+
+    fn pop(&mut self) -> io::Result<Option<T>> {
+        Ok(Vec::pop(self))
+    }
+
+    fn push(&mut self, unit: T) -> io::Result<()> {
+        Vec::push(self, unit);
+        Ok(())
+    }
+}
+
+// Stack based reduction: each unit reacts with the last unit left, and a transformed unit can
+// react again with the one before. on_reaction is given every pair that reacts, in order.
+fn reduce<T, I, S, R, F>(units: I, stack: &mut S, rules: &R, mut on_reaction: F) -> io::Result<()>
+where
+    T: Unit,
+    I: IntoIterator<Item = T>,
+    S: Stack<T>,
+    R: Rules,
+    F: FnMut(T, T, Reaction),
+{
+    for unit in units {
+        let mut current = unit;
+        loop {
+            let reaction = match stack.last()? {
+                Some(previous) => rules
+                    .react(previous.unit(), current.unit())
+                    .map(|reaction| (previous, reaction)),
+                None => None,
+            };
+
+            match reaction {
+                Some((previous, reaction)) => {
+                    stack.pop()?;
+                    on_reaction(previous, current, reaction);
+                    match reaction {
+                        Reaction::Annihilate => break,
+                        Reaction::Transform(product) => current = current.transform(product),
+                    }
+                }
+                None => {
+                    stack.push(current)?;
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Units left in memory, without looking at the reactions.
+fn reduce_units<I, R>(units: I, rules: &R) -> Vec<u8>
+where
+    I: IntoIterator<Item = u8>,
+    R: Rules,
+{
+    let mut reduced = Vec::new();
+    reduce(units, &mut reduced, rules, |_, _, _| {}).expect("In-memory stacks never fail");
+
+    reduced
+}
+
+// Spill files created by the process so far, to give each stack its own.
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// A new file in the shared temporary directory, never one already there.
+fn create_spill_file() -> io::Result<(PathBuf, File)> {
+    for _ in 0..100 {
+        let path = env::temp_dir().join(format!(
+            "day5-stack-{}-{}.bin",
+            process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::AlreadyExists,
+        "No free name for a spill file",
+    ))
+}
+
+// Stack of unreacted units that keeps at most limit units in memory. Past that, the bottom half
+// is spilled to a temporary file, and read back once the units above it have all reacted.
+struct SpillStack {
+    units: Vec<u8>,
+    limit: usize,
+    // Created on the first spill.
+    file: Option<(PathBuf, File)>,
+    spilled: u64,
+}
+
+impl SpillStack {
+    fn new(limit: usize) -> Self {
+        SpillStack {
+            units: Vec::new(),
+            limit: limit.max(2),
+            file: None,
+            spilled: 0,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.spilled + self.units.len() as u64
+    }
+
+    // Read back the top of the spilled units if none are left in memory.
+    fn reload(&mut self) -> io::Result<()> {
+        if !self.units.is_empty() || self.spilled == 0 {
+            return Ok(());
+        }
+        if let Some((_, file)) = self.file.as_mut() {
+            let count = self.spilled.min(self.limit as u64 / 2).max(1);
+            self.spilled -= count;
+
+            self.units.resize(count as usize, 0);
+            file.seek(SeekFrom::Start(self.spilled))?;
+            file.read_exact(&mut self.units)?;
+            file.set_len(self.spilled)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Stack<u8> for SpillStack {
+    fn last(&mut self) -> io::Result<Option<u8>> {
+        self.reload()?;
+        Ok(self.units.as_slice().last().copied())
+    }
+
+    fn pop(&mut self) -> io::Result<Option<u8>> {
+        self.reload()?;
+        Ok(self.units.pop())
+    }
+
+    fn push(&mut self, unit: u8) -> io::Result<()> {
+        self.units.push(unit);
+        if self.units.len() > self.limit {
+            let half = self.units.len() / 2;
+            let (_, file) = match self.file.as_mut() {
+                Some(spill) => spill,
+                None => self.file.insert(create_spill_file()?),
+            };
+
+            file.seek(SeekFrom::Start(self.spilled))?;
+            file.write_all(&self.units[..half])?;
+            self.units.drain(..half);
+            self.spilled += half as u64;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SpillStack {
+    fn drop(&mut self) {
+        if let Some((path, _)) = &self.file {
+            // Nothing more to do if the file can't be removed.
+            let _ = remove_file(path);
+        }
+    }
+}
+
+// Reduce the polymer read in chunks, keeping the unreacted units in a SpillStack, for polymers
+// larger than memory. Returns the number of units left.
+fn reduce_stream<S, R>(mut input: S, rules: &R, limit: usize) -> Result<u64>
+where
+    S: Read,
+    R: Rules,
+{
+    let mut reduced = SpillStack::new(limit);
+    let mut chunk = vec![0u8; 64 * 1024];
+    // Whitespace is only accepted around the polymer.
+    let (mut position, mut started, mut ended) = (0, false, false);
+
+    loop {
+        let read = input.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+
+        let chunk = &chunk[..read];
+        for &unit in chunk {
+            position += 1;
+            if unit.is_ascii_whitespace() {
+                ended = started;
                 continue;
             }
+            if ended {
+                return Err(format!(
+                    "Whitespace inside the polymer before position {}",
+                    position - 1
+                )
+                .into());
+            }
+            if !unit.is_ascii_alphabetic() {
+                return Err(format!(
+                    "Invalid unit {:?} at position {}, polymers are made of ASCII letters",
+                    char::from(unit),
+                    position - 1
+                )
+                .into());
+            }
+            started = true;
         }
 
-        polymer.push(current as char);
+        let units = chunk
+            .iter()
+            .copied()
+            .filter(|unit| !unit.is_ascii_whitespace());
+        reduce(units, &mut reduced, rules, |_, _, _| {})?;
     }
 
+    Ok(reduced.len())
+}
+
+// Two units that reacted, by position in the original polymer. A unit produced by an earlier
+// reaction has the position of the right unit it comes from.
+struct Collision {
+    left: (usize, char),
+    right: (usize, char),
+    reaction: Reaction,
+}
+
+// Record every reaction in trace, if given, in the order they happen.
+fn react_polymer<R: Rules>(input: &str, rules: &R, trace: Option<&mut Vec<Collision>>) -> String {
+    let trace = match trace {
+        None => {
+            return reduce_units(input.bytes(), rules)
+                .into_iter()
+                .map(char::from)
+                .collect()
+        }
+        Some(trace) => trace,
+    };
+
+    let mut polymer: Vec<(usize, u8)> = Vec::with_capacity(input.len());
+    reduce(
+        input.bytes().enumerate(),
+        &mut polymer,
+        rules,
+        |left, right, reaction| {
+            trace.push(Collision {
+                left: (left.0, char::from(left.1)),
+                right: (right.0, char::from(right.1)),
+                reaction,
+            })
+        },
+    )
+    .expect("In-memory stacks never fail");
+
     polymer
+        .into_iter()
+        .map(|(_, unit)| char::from(unit))
+        .collect()
 }
 
-fn part1(input: &str) {
-    let reacted = react_polymer(input);
+// Longest polymer worth printing at each step.
+const WALKTHROUGH_MAX_LENGTH: usize = 1000;
+
+// Print the polymer after each reaction, like the puzzle's walkthrough.
+fn walkthrough<R: Rules>(input: &str, rules: &R) -> Result<()> {
+    let polymer = parse_polymer(input)?;
+    if polymer.len() > WALKTHROUGH_MAX_LENGTH {
+        return Err(format!(
+            "Polymer of {} units, too long to print each step",
+            polymer.len()
+        )
+        .into());
+    }
+
+    let mut trace = Vec::new();
+    react_polymer(polymer, rules, Some(&mut trace));
+
+    let mut units = polymer.chars().map(Some).collect::<Vec<Option<char>>>();
+    println!("{}", polymer);
+    for Collision {
+        left,
+        right,
+        reaction,
+    } in trace
+    {
+        units[left.0] = None;
+        let product = match reaction {
+            Reaction::Annihilate => None,
+            Reaction::Transform(product) => Some(char::from(product)),
+        };
+        units[right.0] = product;
+
+        let product = product.map_or(String::new(), |product| format!(" into {}", product));
+        println!(
+            "{}  {}{} at {} and {} react{}",
+            units.iter().flatten().collect::<String>(),
+            left.1,
+            right.1,
+            left.0,
+            right.0,
+            product
+        );
+    }
+
+    Ok(())
+}
+
+// The product column is empty for units that annihilate.
+fn write_trace(file: &str, trace: &[Collision]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(file)?);
+
+    writeln!(
+        writer,
+        "left_position,left_unit,right_position,right_unit,product"
+    )?;
+    for Collision {
+        left,
+        right,
+        reaction,
+    } in trace
+    {
+        let product = match reaction {
+            Reaction::Annihilate => String::new(),
+            Reaction::Transform(product) => char::from(*product).to_string(),
+        };
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            left.0, left.1, right.0, right.1, product
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn part1(input: &str, trace: Option<&mut Vec<Collision>>) -> String {
+    let reacted = react_polymer(input, &OppositeCase, trace);
 
     assert_eq!(9238, reacted.len());
     println!("Part1 {}", reacted.len());
+
+    reacted
 }
 
-fn part2(input: &str) {
-    let lowest_count = ('a'..='z')
+// Filter the unit type out of the whole polymer and react it again, for each unit type.
+fn shortest_naive(input: &str) -> Option<usize> {
+    ('a'..='z')
         .map(|c| {
             let filtered = input
                 .chars()
                 .filter(|ch| ch.to_ascii_lowercase() != c)
                 .collect::<String>();
 
-            let reacted = react_polymer(&filtered);
-
-            reacted.len()
+            react_polymer(&filtered, &OppositeCase, None).len()
         })
         .min()
-        .unwrap();
+}
+
+// Result of removing a unit type, both cases, before reacting the polymer.
+struct Removal {
+    unit: char,
+    length: usize,
+    // Only kept on request, polymers can be large.
+    polymer: Option<String>,
+}
+
+// Removing units and reacting commute: whatever reacts in the full polymer also reacts once a
+// unit type is removed. Start from the reacted polymer, and try each unit type in its own thread.
+fn removals(reacted: &str, keep_polymers: bool) -> Vec<Removal> {
+    thread::scope(|scope| {
+        let handles = (b'a'..=b'z')
+            .map(|removed| {
+                scope.spawn(move || {
+                    let units = reacted
+                        .bytes()
+                        .filter(|unit| unit.to_ascii_lowercase() != removed);
+                    let polymer = reduce_units(units, &OppositeCase);
+
+                    Removal {
+                        unit: char::from(removed),
+                        length: polymer.len(),
+                        polymer: if keep_polymers {
+                            Some(polymer.into_iter().map(char::from).collect())
+                        } else {
+                            None
+                        },
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Reaction thread panicked"))
+            .collect()
+    })
+}
+
+// The removal leaving the shortest polymer, the first unit type in alphabetical order on ties.
+fn shortest(removals: &[Removal]) -> Option<&Removal> {
+    removals.iter().min_by_key(|removal| removal.length)
+}
+
+fn part2(reacted: &str, explain: bool, keep_polymer: bool) -> Result<()> {
+    let removals = removals(reacted, keep_polymer);
+    let best = shortest(&removals).ok_or("No unit types ?")?;
+
+    assert_eq!(4052, best.length);
+    println!("Part2 {}", best.length);
+
+    if explain {
+        println!(
+            "Removing {}/{} leaves {} units",
+            best.unit,
+            best.unit.to_ascii_uppercase(),
+            best.length
+        );
+        for removal in &removals {
+            println!(
+                "  {}/{}: {}",
+                removal.unit,
+                removal.unit.to_ascii_uppercase(),
+                removal.length
+            );
+        }
+    }
+    if let Some(polymer) = &best.polymer {
+        println!("Polymer {}", polymer);
+    }
+
+    Ok(())
+}
+
+// Random polymer of the given size. Units are drawn from a few unit types only, so that many of
+// them react.
+fn generate_polymer(size: usize) -> String {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+
+    (0..size)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let unit = b'a' + (state % 6) as u8;
+            if state & (1 << 32) == 0 {
+                unit as char
+            } else {
+                unit.to_ascii_uppercase() as char
+            }
+        })
+        .collect()
+}
+
+fn bench(megabytes: usize) -> Result<()> {
+    let polymer = generate_polymer(megabytes * 1024 * 1024);
 
-    assert_eq!(4052, lowest_count);
-    println!("Part2 {}", lowest_count);
+    let start = Instant::now();
+    let naive = shortest_naive(&polymer);
+    println!("Naive: {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let fast = shortest(&removals(
+        &react_polymer(&polymer, &OppositeCase, None),
+        false,
+    ))
+    .map(|best| best.length);
+    println!("Reacted first, parallel: {:?}", start.elapsed());
+
+    if naive != fast {
+        return Err(format!("Results differ: {:?} and {:?}", naive, fast).into());
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
+    // --rules <file>: reduce the input, or the walkthrough's or streamed polymer, with the rules
+    // from the file instead, see RuleTable.
+    // --bench <megabytes>: time part2 on a generated polymer of that size.
+    // --explain: print which unit type to remove, and the length left by removing each one.
+    // --polymer: print the shortest polymer.
+    // --stream <file> [--stack-limit <units>]: react a polymer of any size, '-' for stdin,
+    // keeping at most that many units in memory (1M by default).
+    // --trace <file>: write every reaction of part1 to a CSV file.
+    // --walkthrough <polymer>: print a small polymer after each reaction.
+    let args = env::args().collect::<Vec<String>>();
+
+    let rules = match args.iter().position(|arg| arg == "--rules") {
+        Some(idx) => Some(RuleTable::new(&read_to_string(
+            args.get(idx + 1).ok_or("--rules expects a file name")?,
+        )?)?),
+        None => None,
+    };
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--walkthrough") {
+        let polymer = args.get(idx + 1).ok_or("--walkthrough expects a polymer")?;
+        return match &rules {
+            Some(rules) => walkthrough(polymer, rules),
+            None => walkthrough(polymer, &OppositeCase),
+        };
+    }
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--stream") {
+        let file = args.get(idx + 1).ok_or("--stream expects a file name")?;
+        let limit = match args.iter().position(|arg| arg == "--stack-limit") {
+            Some(idx) => args
+                .get(idx + 1)
+                .ok_or("--stack-limit expects a number of units")?
+                .parse()?,
+            None => 1 << 20,
+        };
+
+        let input: Box<dyn Read> = if file == "-" {
+            Box::new(io::stdin())
+        } else {
+            Box::new(File::open(file)?)
+        };
+        let length = match &rules {
+            Some(rules) => reduce_stream(input, rules, limit)?,
+            None => reduce_stream(input, &OppositeCase, limit)?,
+        };
+        println!("Length {}", length);
+
+        return Ok(());
+    }
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--bench") {
+        let megabytes = match args.get(idx + 1) {
+            Some(megabytes) => megabytes.parse()?,
+            None => 4,
+        };
+        return bench(megabytes);
+    }
+
     let input = read_to_string("input.txt")?;
 
-    part1(&input);
-    part2(&input);
+    if let Some(rules) = &rules {
+        let reduced = reduce_units(input.trim().bytes(), rules);
+        println!("Reduced {}", String::from_utf8_lossy(&reduced));
+        println!("Length {}", reduced.len());
+
+        return Ok(());
+    }
+
+    let polymer = parse_polymer(&input)?;
+
+    let trace_file = match args.iter().position(|arg| arg == "--trace") {
+        Some(idx) => Some(args.get(idx + 1).ok_or("--trace expects a file name")?),
+        None => None,
+    };
+    let mut trace = Vec::new();
+
+    let reacted = part1(polymer, trace_file.map(|_| &mut trace));
+    if let Some(file) = trace_file {
+        write_trace(file, &trace)?;
+    }
+    part2(
+        &reacted,
+        args.iter().any(|arg| arg == "--explain"),
+        args.iter().any(|arg| arg == "--polymer"),
+    )?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: [usize; 8] = [1, 2, 3, 4, 7, 64, 1000, 1 << 20];
+
+    #[test]
+    fn spill_stack_is_last_in_first_out() {
+        for &limit in &LIMITS {
+            let mut stack = SpillStack::new(limit);
+            let units = (0..5000).map(|idx| (idx % 251) as u8).collect::<Vec<u8>>();
+
+            for &unit in &units {
+                stack.push(unit).unwrap();
+            }
+            assert_eq!(stack.len(), units.len() as u64);
+            for &unit in units.iter().rev() {
+                assert_eq!(stack.last().unwrap(), Some(unit));
+                assert_eq!(stack.pop().unwrap(), Some(unit));
+            }
+            assert_eq!(stack.pop().unwrap(), None);
+            assert_eq!(stack.len(), 0);
+        }
+    }
+
+    #[test]
+    fn spill_stacks_use_their_own_files() {
+        let (mut first, mut second) = (SpillStack::new(2), SpillStack::new(2));
+        for unit in 0..100 {
+            first.push(unit).unwrap();
+            second.push(200 - unit).unwrap();
+        }
+
+        for unit in (0..100).rev() {
+            assert_eq!(first.pop().unwrap(), Some(unit));
+            assert_eq!(second.pop().unwrap(), Some(200 - unit));
+        }
+    }
+
+    #[test]
+    fn stream_matches_in_memory_reduction() {
+        let polymer = generate_polymer(200_000);
+        let expected = reduce_units(polymer.bytes(), &OppositeCase).len() as u64;
+
+        for &limit in &LIMITS {
+            assert_eq!(
+                reduce_stream(polymer.as_bytes(), &OppositeCase, limit).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn stream_reloads_nested_polymers() {
+        // abc…zZY…CBA, repeated inside itself: the stack grows to its full depth before every
+        // unit reacts, reloading each spilled half in turn.
+        let half = (0..20_000)
+            .map(|idx| b'a' + (idx % 26) as u8)
+            .collect::<Vec<u8>>();
+        let mut polymer = half.clone();
+        polymer.extend(half.iter().rev().map(u8::to_ascii_uppercase));
+
+        for &limit in &LIMITS {
+            assert_eq!(
+                reduce_stream(polymer.as_slice(), &OppositeCase, limit).unwrap(),
+                0
+            );
+        }
+        polymer.push(b'x');
+        assert_eq!(
+            reduce_stream(polymer.as_slice(), &OppositeCase, 5).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn stream_rejects_whitespace_inside() {
+        assert_eq!(
+            reduce_stream(&b"  aAbB\n"[..], &OppositeCase, 2).unwrap(),
+            0
+        );
+        assert!(reduce_stream(&b"aA bB"[..], &OppositeCase, 2).is_err());
+        assert!(reduce_stream(&b"aA1"[..], &OppositeCase, 2).is_err());
+    }
+
+    #[test]
+    fn trace_follows_every_reaction() {
+        let mut trace = Vec::new();
+        let reacted = react_polymer("dabAcCaCBAcCcaDA", &OppositeCase, Some(&mut trace));
+
+        assert_eq!(reacted, "dabCBAcaDA");
+        assert_eq!(
+            trace
+                .iter()
+                .map(|collision| (collision.left.0, collision.right.0))
+                .collect::<Vec<_>>(),
+            [(4, 5), (3, 6), (10, 11)]
+        );
+
+        let rules = RuleTable::new("ab c\ncc").unwrap();
+        let mut trace = Vec::new();
+        assert_eq!(react_polymer("xabcy", &rules, Some(&mut trace)), "xy");
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[0].left, trace[0].right), ((1, 'a'), (2, 'b')));
+        assert_eq!(trace[0].reaction, Reaction::Transform(b'c'));
+        assert_eq!((trace[1].left, trace[1].right), ((2, 'c'), (3, 'c')));
+        assert_eq!(trace[1].reaction, Reaction::Annihilate);
+    }
+}
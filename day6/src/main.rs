@@ -1,48 +1,76 @@
-use std::cmp::{max, min};
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
 use std::fs::read_to_string;
 
 type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
 #[derive(Debug, Clone)]
 struct Location {
-    x: usize,
-    y: usize,
+    x: i64,
+    y: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct Square {
     id: usize,
     distance: usize,
-    total: usize,
 }
 
-type Area = Vec<Vec<Square>>;
+// Bounding box of the locations, squares[x][y] is the square at (x0 + x, y0 + y).
+struct Area {
+    x0: i64,
+    y0: i64,
+    squares: Vec<Vec<Square>>,
+}
 
-fn new_area(x: usize, y: usize) -> Area {
-    vec![
-        vec![
-            Square {
-                id: 0,
-                distance: usize::MAX,
-                total: 0,
-            };
-            y + 1
-        ];
-        x + 1
-    ]
+fn new_area(locations: &[Location]) -> Area {
+    let x0 = locations
+        .iter()
+        .map(|location| location.x)
+        .min()
+        .unwrap_or(0);
+    let y0 = locations
+        .iter()
+        .map(|location| location.y)
+        .min()
+        .unwrap_or(0);
+    let x1 = locations
+        .iter()
+        .map(|location| location.x)
+        .max()
+        .unwrap_or(0);
+    let y1 = locations
+        .iter()
+        .map(|location| location.y)
+        .max()
+        .unwrap_or(0);
+
+    Area {
+        x0,
+        y0,
+        squares: vec![
+            vec![
+                Square {
+                    id: 0,
+                    distance: usize::MAX,
+                };
+                (y1 - y0 + 1) as usize
+            ];
+            (x1 - x0 + 1) as usize
+        ],
+    }
 }
 
-fn get_distance(location: &Location, x: usize, y: usize) -> usize {
-    max(location.x, x) - min(location.x, x) + max(location.y, y) - min(location.y, y)
+fn get_distance(location: &Location, x: i64, y: i64) -> usize {
+    ((location.x - x).unsigned_abs() + (location.y - y).unsigned_abs()) as usize
 }
 
 fn mark_with_location(area: &mut Area, id: usize, location: &Location) {
-    for (x, row) in area.iter_mut().enumerate() {
+    let (x0, y0) = (area.x0, area.y0);
+    for (x, row) in area.squares.iter_mut().enumerate() {
         for (y, square) in row.iter_mut().enumerate() {
-            let distance = get_distance(location, x, y);
+            let distance = get_distance(location, x0 + x as i64, y0 + y as i64);
 
-            square.total += distance;
             if square.distance > distance {
                 square.id = id;
                 square.distance = distance;
@@ -55,6 +83,97 @@ fn mark_with_location(area: &mut Area, id: usize, location: &Location) {
     }
 }
 
+// Multi-source BFS from all locations at once, assigning each square to its nearest location in
+// a single pass over the area. Without obstacles, the BFS distance is the Manhattan distance.
+fn mark_nearest(area: &mut Area, locations: &[Location]) {
+    let (x0, y0) = (area.x0, area.y0);
+    let area = &mut area.squares;
+    let mut queue = VecDeque::new();
+    for (id, location) in locations.iter().enumerate() {
+        let (x, y) = ((location.x - x0) as usize, (location.y - y0) as usize);
+        let square = &mut area[x][y];
+        // Two locations on the same square tie.
+        square.id = if square.distance == 0 { usize::MAX } else { id };
+        square.distance = 0;
+        queue.push_back((x, y));
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let Square { id, distance, .. } = area[x][y];
+
+        let mut neighbours = Vec::with_capacity(4);
+        if x > 0 {
+            neighbours.push((x - 1, y));
+        }
+        if x + 1 < area.len() {
+            neighbours.push((x + 1, y));
+        }
+        if y > 0 {
+            neighbours.push((x, y - 1));
+        }
+        if y + 1 < area[x].len() {
+            neighbours.push((x, y + 1));
+        }
+
+        for (next_x, next_y) in neighbours {
+            let next = &mut area[next_x][next_y];
+            if next.distance == usize::MAX {
+                next.id = id;
+                next.distance = distance + 1;
+                queue.push_back((next_x, next_y));
+            } else if next.distance == distance + 1 && next.id != id {
+                // Reached as fast from two different locations, or from a tied square.
+                next.id = usize::MAX;
+            }
+        }
+    }
+}
+
+// Total distance along one axis to all locations, from each coordinate of start..=end.
+fn axis_totals(
+    locations: &[Location],
+    start: i64,
+    end: i64,
+    coordinate: fn(&Location) -> i64,
+) -> Vec<usize> {
+    (start..=end)
+        .map(|position| {
+            locations
+                .iter()
+                .map(|location| (coordinate(location) - position).unsigned_abs() as usize)
+                .sum::<usize>()
+        })
+        .collect()
+}
+
+// Number of squares whose total distance to all locations is below safe_total. A square d away
+// from the locations' bounding box is at least d away from each of them, so the region lies within
+// (safe_total - 1) / n of the box. A square's total is its total along x plus its total along y.
+fn safe_region_size(locations: &[Location], safe_total: usize) -> usize {
+    if locations.is_empty() {
+        return 0;
+    }
+    let padding = (safe_total.saturating_sub(1) / locations.len()) as i64;
+    let bounds = |coordinate: fn(&Location) -> i64| {
+        let coordinates = locations.iter().map(coordinate);
+        (
+            coordinates.clone().min().unwrap_or(0) - padding,
+            coordinates.max().unwrap_or(0) + padding,
+        )
+    };
+
+    let (x0, x1) = bounds(|location| location.x);
+    let (y0, y1) = bounds(|location| location.y);
+    let x_totals = axis_totals(locations, x0, x1, |location| location.x);
+    let mut y_totals = axis_totals(locations, y0, y1, |location| location.y);
+    y_totals.sort_unstable();
+
+    x_totals
+        .iter()
+        .map(|&x_total| y_totals.partition_point(|&y_total| x_total + y_total < safe_total))
+        .sum()
+}
+
 fn get_location(line: &str) -> Result<Location> {
     let mut split = line.split(", ");
 
@@ -62,32 +181,37 @@ fn get_location(line: &str) -> Result<Location> {
         x: split
             .next()
             .ok_or("Location parsing failure")?
-            .parse::<usize>()?,
+            .parse::<i64>()?,
         y: split
             .next()
             .ok_or("Location parsing failure")?
-            .parse::<usize>()?
+            .parse::<i64>()?,
     })
 }
 
-fn get_marked_area(input: &str) -> Result<Area> {
-    let mut locations = Vec::new();
-    let (mut max_x, mut max_y) = (0, 0);
-    for line in input.lines() {
-        let location = get_location(line)?;
+enum Voronoi {
+    // Measure the distance from every location to every square.
+    BruteForce,
+    Bfs,
+}
 
-        max_x = max(max_x, location.x);
-        max_y = max(max_y, location.y);
-        locations.push(location);
-    }
+fn get_locations(input: &str) -> Result<Vec<Location>> {
+    input.lines().map(get_location).collect()
+}
 
-    let mut area = new_area(max_x, max_y);
-    for (id, location) in locations.iter().enumerate() {
-        // Mark the area using each point. 0 for the point, 1 around it, then 2
-        mark_with_location(&mut area, id, &location);
+fn get_marked_area(locations: &[Location], voronoi: Voronoi) -> Area {
+    let mut area = new_area(locations);
+    match voronoi {
+        Voronoi::BruteForce => {
+            for (id, location) in locations.iter().enumerate() {
+                // Mark the area using each point. 0 for the point, 1 around it, then 2
+                mark_with_location(&mut area, id, location);
+            }
+        }
+        Voronoi::Bfs => mark_nearest(&mut area, locations),
     }
 
-    Ok(area)
+    area
 }
 
 fn part1(area: &Area) -> Result<()> {
@@ -95,9 +219,10 @@ fn part1(area: &Area) -> Result<()> {
     // wouldn't count towards the largest finite region.
     let mut area_sizes: HashMap<usize, usize> = HashMap::new();
     let mut infinite = HashSet::new();
-    for (x, row) in area.iter().enumerate() {
+    let squares = &area.squares;
+    for (x, row) in squares.iter().enumerate() {
         for (y, square) in row.iter().enumerate() {
-            if x == 0 || y == 0 || x == area.len() - 1 || y == row.len() - 1 {
+            if x == 0 || y == 0 || x == squares.len() - 1 || y == row.len() - 1 {
                 infinite.insert(square.id);
             }
             if square.id != usize::MAX {
@@ -108,7 +233,7 @@ fn part1(area: &Area) -> Result<()> {
 
     let largest = area_sizes
         .iter()
-        .filter(|(id, _)| infinite.get(id).is_none())
+        .filter(|(id, _)| !infinite.contains(id))
         .map(|(_, &value)| value)
         .max()
         .ok_or("Largest not found")?;
@@ -119,22 +244,124 @@ fn part1(area: &Area) -> Result<()> {
     Ok(())
 }
 
-fn part2(area: &Area) {
-    let count = area
-        .iter()
-        .map(|row| row.iter().filter(|square| square.total < 10000).count())
-        .sum::<usize>();
+fn part2(locations: &[Location]) {
+    let count = safe_region_size(locations, 10000);
 
     assert_eq!(36136, count);
     println!("Part2 {}", count);
 }
 
 fn main() -> Result<()> {
+    // --voronoi <brute-force|bfs>: how squares are assigned to locations, bfs by default.
+    let args = env::args().collect::<Vec<String>>();
+    let voronoi = match option_value(&args, "--voronoi")? {
+        None | Some("bfs") => Voronoi::Bfs,
+        Some("brute-force") => Voronoi::BruteForce,
+        Some(voronoi) => return Err(format!("Unknown algorithm {}", voronoi).into()),
+    };
+
     let input = read_to_string("input.txt")?;
 
-    let area = get_marked_area(&input)?;
-    part1(&area)?;
-    part2(&area);
+    let locations = get_locations(&input)?;
+    part1(&get_marked_area(&locations, voronoi))?;
+    part2(&locations);
 
     Ok(())
 }
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => Ok(Some(
+            args.get(idx + 1)
+                .ok_or(format!("{} expects a value", name))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same_areas(input: &str) {
+        let locations = get_locations(input).unwrap();
+        let bfs = get_marked_area(&locations, Voronoi::Bfs);
+        let brute_force = get_marked_area(&locations, Voronoi::BruteForce);
+
+        assert_eq!((bfs.x0, bfs.y0), (brute_force.x0, brute_force.y0));
+        assert_eq!(bfs.squares, brute_force.squares, "for {:?}", input);
+    }
+
+    #[test]
+    fn equidistant_ties() {
+        assert_same_areas("0, 0\n4, 0");
+        assert_same_areas("0, 0\n4, 4\n0, 4\n4, 0");
+
+        let area = get_marked_area(&get_locations("0, 0\n4, 0").unwrap(), Voronoi::Bfs);
+        assert_eq!(area.squares[2][0].id, usize::MAX);
+        assert_eq!(area.squares[1][0].id, 0);
+        assert_eq!(area.squares[3][0].id, 1);
+    }
+
+    #[test]
+    fn duplicate_locations() {
+        assert_same_areas("3, 3\n3, 3\n0, 0");
+        assert_same_areas("1, 2\n1, 2");
+
+        let area = get_marked_area(&get_locations("3, 3\n3, 3\n0, 0").unwrap(), Voronoi::Bfs);
+        assert_eq!(area.squares[3][3].id, usize::MAX);
+        assert_eq!(area.squares[3][3].distance, 0);
+    }
+
+    #[test]
+    fn random_layouts() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |bound: i64| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as i64
+        };
+
+        for _ in 0..300 {
+            let count = 1 + next(20) as usize;
+            let size = 1 + next(40);
+            let mut locations = (0..count)
+                .map(|_| format!("{}, {}", next(size) - size / 2, next(size)))
+                .collect::<Vec<String>>();
+            // Sometimes two locations on the same square.
+            if next(4) == 0 {
+                locations.push(locations[0].clone());
+            }
+
+            assert_same_areas(&locations.join("\n"));
+        }
+    }
+
+    #[test]
+    fn safe_region_past_the_locations() {
+        let sample = get_locations("1, 1\n1, 6\n8, 3\n3, 4\n5, 5\n8, 9").unwrap();
+        assert_eq!(safe_region_size(&sample, 32), 16);
+
+        // A diamond of radius 9999 around the only location.
+        let single = get_locations("5, 5").unwrap();
+        assert_eq!(
+            safe_region_size(&single, 10000),
+            2 * 9999 * 9999 + 2 * 9999 + 1
+        );
+
+        let offset = get_locations("-1000000, 20\n-1000003, 24").unwrap();
+        let count = (-1000100..-999900)
+            .flat_map(|x| (-100..150).map(move |y| (x, y)))
+            .filter(|&(x, y)| {
+                offset
+                    .iter()
+                    .map(|location| get_distance(location, x, y))
+                    .sum::<usize>()
+                    < 40
+            })
+            .count();
+        assert_eq!(safe_region_size(&offset, 40), count);
+    }
+}